@@ -61,6 +61,33 @@ pub fn king(sq: Square) -> Bitboard {
     KING[sq]
 }
 
+/// The squares strictly between two squares on the same rank, file or diagonal, or none if the
+/// squares are not aligned.
+#[inline(always)]
+pub fn between(a: Square, b: Square) -> Bitboard {
+    let (a_bb, b_bb) = (Bitboard::of_sq(a), Bitboard::of_sq(b));
+    if rook(a, Bitboard::empty()).contains(b) {
+        rook(a, b_bb) & rook(b, a_bb)
+    } else if bishop(a, Bitboard::empty()).contains(b) {
+        bishop(a, b_bb) & bishop(b, a_bb)
+    } else {
+        Bitboard::empty()
+    }
+}
+
+/// The whole rank, file or diagonal through two squares, or none if the squares are not aligned.
+#[inline(always)]
+pub fn line(a: Square, b: Square) -> Bitboard {
+    let ends = Bitboard::of_sq(a) | Bitboard::of_sq(b);
+    if rook(a, Bitboard::empty()).contains(b) {
+        rook(a, Bitboard::empty()) & rook(b, Bitboard::empty()) | ends
+    } else if bishop(a, Bitboard::empty()).contains(b) {
+        bishop(a, Bitboard::empty()) & bishop(b, Bitboard::empty()) | ends
+    } else {
+        Bitboard::empty()
+    }
+}
+
 pub const KING: [Bitboard; 64] = [
     Bitboard(0x0000000000000302), Bitboard(0x0000000000000705), Bitboard(0x0000000000000e0a), Bitboard(0x0000000000001c14),
     Bitboard(0x0000000000003828), Bitboard(0x0000000000007050), Bitboard(0x000000000000e0a0), Bitboard(0x000000000000c040),
@@ -97,4 +124,27 @@ pub const KNIGHT: [Bitboard; 64] = [
     Bitboard(0x4400442800000000), Bitboard(0x8800885000000000), Bitboard(0x100010a000000000), Bitboard(0x2000204000000000),
     Bitboard(0x0004020000000000), Bitboard(0x0008050000000000), Bitboard(0x00110a0000000000), Bitboard(0x0022140000000000),
     Bitboard(0x0044280000000000), Bitboard(0x0088500000000000), Bitboard(0x0010a00000000000), Bitboard(0x0020400000000000)
-];
\ No newline at end of file
+];
+#[cfg(test)]
+mod tests {
+    use crate::attacks::{between, line};
+    use crate::types::bitboard::Bitboard;
+    use crate::types::square::Square;
+
+    #[test]
+    fn test_between() {
+        assert_eq!(between(Square(0), Square(3)), Bitboard(0b110));
+        assert_eq!(between(Square(0), Square(1)), Bitboard::empty());
+        assert_eq!(between(Square(0), Square(63)).count(), 6);
+        assert_eq!(between(Square(6), Square(21)), Bitboard::empty());
+    }
+
+    #[test]
+    fn test_line() {
+        assert_eq!(line(Square(0), Square(3)), Bitboard(0xFF));
+        assert_eq!(line(Square(9), Square(0)), Bitboard(0x8040201008040201));
+        assert_eq!(line(Square(4), Square(60)), Bitboard(0x1010101010101010));
+        assert_eq!(line(Square(6), Square(21)), Bitboard::empty());
+    }
+
+}
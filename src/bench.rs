@@ -63,7 +63,7 @@ pub fn bench() {
     let mut time: u64 = 0;
 
     for fen in FENS {
-        let board = Board::from_fen(fen);
+        let board = Board::from_fen(fen).unwrap();
         let mut td = ThreadData::with_depth_limit(BENCH_DEPTH);
         search(&board, &mut td);
         nodes += td.nodes;
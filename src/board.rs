@@ -1,4 +1,4 @@
-use crate::movegen::{is_attacked, is_check};
+use crate::movegen::is_attacked;
 use crate::types::bitboard::Bitboard;
 use crate::types::piece::Piece;
 use crate::types::piece::Piece::{King, Pawn};
@@ -9,8 +9,9 @@ use crate::types::{File, Rank};
 use crate::zobrist::Zobrist;
 use crate::{attacks, fen};
 use crate::{moves::Move, moves::MoveFlag};
+use std::fmt;
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq)]
 pub struct Board {
     pub bb: [Bitboard; 8],         // bitboards for each piece type (0-5) and for both colours (6-7)
     pub pcs: [Option<Piece>; 64],  // piece type on each square
@@ -24,6 +25,17 @@ pub struct Board {
     pub non_pawn_hashes: [u64; 2], // Zobrist hashes for non-pawns
     pub major_hash: u64,           // Zobrist hash for major pieces
     pub minor_hash: u64,           // Zobrist hash for minor pieces
+    pub checkers: Bitboard,        // enemy pieces giving check to the side to move
+    pub pinned: Bitboard,          // our pieces pinned to our king
+    pub discoverers: Bitboard,     // our pieces blocking one of our sliders from the enemy king
+    pub check_squares: [Bitboard; 6], // squares from which each of our piece types would give check
+}
+
+/// The record needed to unmake a move. Copying the whole board is as fast as saving only the state
+/// lost by the move and replaying it in reverse, and much simpler.
+#[derive(Clone, Copy)]
+pub struct Undo {
+    board: Board,
 }
 
 impl Default for Board {
@@ -35,7 +47,7 @@ impl Default for Board {
 impl Board {
 
     pub fn new() -> Board {
-        Board::from_fen(fen::STARTPOS)
+        Board::from_fen(fen::STARTPOS).unwrap()
     }
 
     pub fn empty() -> Board {
@@ -52,16 +64,22 @@ impl Board {
             non_pawn_hashes: [0, 0],
             major_hash: 0,
             minor_hash: 0,
+            checkers: Bitboard::empty(),
+            pinned: Bitboard::empty(),
+            discoverers: Bitboard::empty(),
+            check_squares: [Bitboard::empty(); 6],
         }
     }
 
-    pub fn make(&mut self, m: &Move) {
+    /// Make the move, returning the record needed to unmake it again.
+    pub fn make(&mut self, m: &Move) -> Undo {
 
         let side = self.stm;
         let (from, to, flag) = (m.from(), m.to(), m.flag());
         let pc = self.piece_at(from).unwrap();
         let new_pc = if let Some(promo) = m.promo_piece() { promo } else { pc };
         let captured = if flag == MoveFlag::EnPassant { Some(Piece::Pawn) } else { self.pcs[to] };
+        let undo = Undo { board: *self };
 
         self.toggle_sq(from, pc, side);
         if let Some(captured) = captured {
@@ -81,15 +99,116 @@ impl Board {
         self.hm = if captured.is_some() || pc == Piece::Pawn { 0 } else { self.hm + 1 };
         self.hash ^= Zobrist::stm();
         self.stm = self.stm.flip();
+        self.update_check_info();
+        undo
 
     }
 
+    /// Take back a move made with `make`, restoring the board exactly as it was.
+    pub fn unmake(&mut self, _m: &Move, undo: Undo) {
+        *self = undo.board;
+    }
+
+    /// Recompute the cached checkers, pins and check squares for the side to move.
+    pub fn update_check_info(&mut self) {
+        let side = self.stm;
+        let occ = self.occ();
+        let king_sq = self.king_sq(side);
+        let their_king_sq = self.king_sq(side.flip());
+
+        self.checkers = self.attackers_to(king_sq, occ) & self.them();
+        self.pinned = self.slider_blockers(king_sq, side.flip()) & self.us();
+        self.discoverers = self.slider_blockers(their_king_sq, side) & self.us();
+
+        let bishop_checks = attacks::bishop(their_king_sq, occ);
+        let rook_checks = attacks::rook(their_king_sq, occ);
+        self.check_squares = [
+            attacks::pawn(their_king_sq, side.flip()),
+            attacks::knight(their_king_sq),
+            bishop_checks,
+            rook_checks,
+            bishop_checks | rook_checks,
+            Bitboard::empty(),
+        ];
+    }
+
+    /// All pieces of either side attacking the given square.
+    pub fn attackers_to(&self, sq: Square, occ: Bitboard) -> Bitboard {
+        (attacks::pawn(sq, White) & self.pawns(Black))
+            | (attacks::pawn(sq, Black) & self.pawns(White))
+            | (attacks::knight(sq) & self.pcs(Piece::Knight))
+            | (attacks::bishop(sq, occ) & (self.pcs(Piece::Bishop) | self.pcs(Piece::Queen)))
+            | (attacks::rook(sq, occ) & (self.pcs(Piece::Rook) | self.pcs(Piece::Queen)))
+            | (attacks::king(sq) & self.pcs(Piece::King))
+    }
+
+    /// Pieces of either side that are the only piece between the given square and one of the
+    /// sliders of the given side.
+    fn slider_blockers(&self, sq: Square, slider_side: Side) -> Bitboard {
+        let diagonals = self.bishops(slider_side) | self.queens(slider_side);
+        let orthogonals = self.rooks(slider_side) | self.queens(slider_side);
+        let snipers = (attacks::bishop(sq, Bitboard::empty()) & diagonals)
+            | (attacks::rook(sq, Bitboard::empty()) & orthogonals);
+
+        let occ = self.occ();
+        let mut blockers = Bitboard::empty();
+        for sniper in snipers {
+            let between = attacks::between(sq, sniper) & occ;
+            if between.count() == 1 {
+                blockers |= between;
+            }
+        }
+        blockers
+    }
+
+    /// Whether the move checks the enemy king, either directly or by discovery, without making it.
+    pub fn gives_check(&self, mv: &Move) -> bool {
+        let (from, to) = (mv.from(), mv.to());
+        let Some(pc) = self.piece_at(from) else {
+            return false;
+        };
+        let their_king_sq = self.king_sq(self.stm.flip());
+        let occ = self.occ();
+
+        // Direct check. A promoted piece may check along the line through the square it left.
+        if let Some(promo) = mv.promo_piece() {
+            let occ = occ ^ Bitboard::of_sq(from);
+            if attacks::attacks(to, promo, self.stm, occ).contains(their_king_sq) {
+                return true;
+            }
+        } else if self.check_squares[pc].contains(to) {
+            return true;
+        }
+
+        // Discovered check, by moving off the line between one of our sliders and their king
+        if self.discoverers.contains(from) && !attacks::line(from, their_king_sq).contains(to) {
+            return true;
+        }
+
+        if mv.is_ep() {
+            // Removing the captured pawn can also open a line to their king
+            let captured = self.ep_capture_sq(to);
+            let occ = occ ^ Bitboard::of_sq(from) ^ Bitboard::of_sq(captured) | Bitboard::of_sq(to);
+            let diagonals = self.bishops(self.stm) | self.queens(self.stm);
+            let orthogonals = self.rooks(self.stm) | self.queens(self.stm);
+            return !(attacks::bishop(their_king_sq, occ) & diagonals).is_empty()
+                || !(attacks::rook(their_king_sq, occ) & orthogonals).is_empty();
+        }
+
+        if mv.is_castle() {
+            // The rook may give check from its new square
+            let (rook_from, rook_to) = self.rook_sqs(to);
+            let occ = occ ^ Bitboard::of_sq(from) ^ Bitboard::of_sq(to)
+                ^ Bitboard::of_sq(rook_from) ^ Bitboard::of_sq(rook_to);
+            return attacks::rook(rook_to, occ).contains(their_king_sq);
+        }
+
+        false
+    }
+
     #[inline]
     pub fn toggle_sq(&mut self, sq: Square, pc: Piece, side: Side) {
-        let bb: Bitboard = Bitboard::of_sq(sq);
-        self.bb[pc] ^= bb;
-        self.bb[side.idx()] ^= bb;
-        self.pcs[sq] = if self.pcs[sq] == Some(pc) { None } else { Some(pc) };
+        self.toggle_pc(sq, pc, side);
         self.hash ^= Zobrist::sq(pc, side, sq);
         if pc == Pawn {
             self.pawn_hash ^= Zobrist::sq(Pawn, side, sq);
@@ -104,6 +223,15 @@ impl Board {
         }
     }
 
+    /// Toggle a piece on a square without updating the hashes.
+    #[inline]
+    fn toggle_pc(&mut self, sq: Square, pc: Piece, side: Side) {
+        let bb: Bitboard = Bitboard::of_sq(sq);
+        self.bb[pc] ^= bb;
+        self.bb[side.idx()] ^= bb;
+        self.pcs[sq] = if self.pcs[sq] == Some(pc) { None } else { Some(pc) };
+    }
+
     #[inline]
     pub fn toggle_sqs(&mut self, from: Square, to: Square, piece: Piece, side: Side) {
         self.toggle_sq(from, piece, side);
@@ -149,12 +277,12 @@ impl Board {
 
     #[inline]
     fn calc_ep(&mut self, flag: MoveFlag, sq: Square) -> Option<Square>{
-        if self.ep_sq.is_some() {
-            self.hash ^= Zobrist::ep(self.ep_sq.unwrap());
+        if let Some(ep_sq) = self.ep_sq {
+            self.hash ^= Zobrist::ep(ep_sq);
         }
         let ep_sq = if flag == MoveFlag::DoublePush { Some(self.ep_capture_sq(sq)) } else { None };
-        if ep_sq.is_some() {
-            self.hash ^= Zobrist::ep(ep_sq.unwrap());
+        if let Some(ep_sq) = ep_sq {
+            self.hash ^= Zobrist::ep(ep_sq);
         }
         ep_sq
     }
@@ -175,7 +303,8 @@ impl Board {
         }
     }
 
-    pub fn make_null_move(&mut self) {
+    pub fn make_null_move(&mut self) -> Undo {
+        let undo = Undo { board: *self };
         self.hm = 0;
         self.stm = self.stm.flip();
         self.hash ^= Zobrist::stm();
@@ -183,6 +312,12 @@ impl Board {
             self.hash ^= Zobrist::ep(ep_sq);
             self.ep_sq = None;
         }
+        self.update_check_info();
+        undo
+    }
+
+    pub fn unmake_null_move(&mut self, undo: Undo) {
+        *self = undo.board;
     }
 
     pub fn pawns(self, side: Side) -> Bitboard {
@@ -273,34 +408,6 @@ impl Board {
         self.our(Piece::King) | self.our(Piece::Pawn) != self.us()
     }
 
-    pub fn is_fifty_move_rule(self) -> bool {
-        self.hm >= 100
-    }
-
-    pub fn is_insufficient_material(&self) -> bool {
-        let pawns    = self.bb[Piece::Pawn];
-        let knights  = self.bb[Piece::Knight];
-        let bishops  = self.bb[Piece::Bishop];
-        let rooks    = self.bb[Piece::Rook];
-        let queens   = self.bb[Piece::Queen];
-
-        if !(pawns | rooks | queens).is_empty() {
-            return false;
-        }
-
-        let minor_pieces = knights | bishops;
-        let piece_count = minor_pieces.count();
-        if piece_count <= 1 {
-            return true;
-        }
-
-        if knights.is_empty() && !bishops.is_empty()
-            && (bishops & self.white()).count() == 2 || (bishops & self.black()).count() == 2 {
-            return false;
-        }
-        piece_count <= 3
-    }
-
     pub fn is_pseudo_legal(&self, mv: &Move) -> bool {
 
         if !mv.exists() {
@@ -408,7 +515,7 @@ impl Board {
 
             if mv.is_ep() {
                 // Cannot en passant if no en passant square
-                if self.ep_sq.is_none() {
+                if self.ep_sq != Some(to) {
                     return false;
                 }
 
@@ -431,7 +538,7 @@ impl Board {
             let promo_rank = if self.stm == White { Rank::Eight } else { Rank::One };
 
             // Cannot promote a pawn if not to the promotion rank
-            if mv.is_promo() && !promo_rank.to_bb().contains(to) {
+            if mv.is_promo() != promo_rank.to_bb().contains(to) {
                 return false;
             }
 
@@ -440,9 +547,10 @@ impl Board {
 
             if from_file != to_file {
 
-                // Must capture on an adjacent file
-                if to_file as usize != from_file as usize + 1
-                    && to_file as usize != from_file as usize - 1 {
+                // Must capture on an adjacent file, one rank forward
+                if (to_file as u8).abs_diff(from_file as u8) != 1
+                    || (to_rank as u8).abs_diff(from_rank as u8) != 1
+                    || mv.is_double_push() {
                     return false;
                 }
 
@@ -452,7 +560,7 @@ impl Board {
             } else {
 
                 // Cannot capture a piece with a pawn push
-                if captured.is_some() {
+                if captured.is_some() || mv.is_ep() {
                     return false;
                 }
 
@@ -494,14 +602,83 @@ impl Board {
         }
     }
 
+    /// Whether a pseudo-legal move leaves our king safe, using the cached checkers and pins.
     pub fn is_legal(&self, mv: &Move) -> bool {
-        let mut new_board = *self;
-        new_board.make(mv);
-        !is_check(&new_board, self.stm)
+        let (from, to) = (mv.from(), mv.to());
+        let king_sq = self.king_sq(self.stm);
+
+        if from == king_sq {
+            // Castling through check is already ruled out by pseudo-legality
+            if mv.is_castle() {
+                return true;
+            }
+            let occ = self.occ() ^ Bitboard::of_sq(from);
+            return (self.attackers_to(to, occ) & self.them()).is_empty();
+        }
+
+        if mv.is_ep() {
+            // Both pawns leave the rank at once, so look for any attacker once the move is made
+            let captured = self.ep_capture_sq(to);
+            let occ = self.occ() ^ Bitboard::of_sq(from) ^ Bitboard::of_sq(captured) | Bitboard::of_sq(to);
+            let them = self.them() ^ Bitboard::of_sq(captured);
+            return (self.attackers_to(king_sq, occ) & them).is_empty();
+        }
+
+        match self.checkers.count() {
+            0 => {}
+            1 => {
+                // A single check must be captured or blocked
+                let checker = self.checkers.lsb();
+                if !(attacks::between(king_sq, checker) | self.checkers).contains(to) {
+                    return false;
+                }
+            }
+            _ => return false,
+        }
+
+        !self.pinned.contains(from) || attacks::line(king_sq, from).contains(to)
     }
 
 }
 
+/// A diagram of the board followed by the state that isn't visible in it, for debugging.
+impl fmt::Display for Board {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        const SEPARATOR: &str = " +---+---+---+---+---+---+---+---+";
+        writeln!(f, "{}", SEPARATOR)?;
+        for rank in (0..8).rev() {
+            for file in 0..8 {
+                let sq = Square::from(File::parse(file), Rank::parse(rank));
+                let ch = match (self.piece_at(sq), self.side_at(sq)) {
+                    (Some(pc), Some(side)) => fen::piece_to_char(pc, side),
+                    _ => ' ',
+                };
+                write!(f, " | {}", ch)?;
+            }
+            writeln!(f, " | {}", rank + 1)?;
+            writeln!(f, "{}", SEPARATOR)?;
+        }
+        writeln!(f, "   a   b   c   d   e   f   g   h")?;
+        writeln!(f)?;
+
+        let fen = self.to_fen();
+        let fields: Vec<&str> = fen.split_whitespace().collect();
+        let checkers: Vec<String> = self.checkers.map(fen::square_to_string).collect();
+        writeln!(f, "Fen: {}", fen)?;
+        writeln!(f, "Side to move: {:?}", self.stm)?;
+        writeln!(f, "Castling: {}", fields[2])?;
+        writeln!(f, "En passant: {}", fields[3])?;
+        writeln!(f, "Halfmove clock: {}", self.hm)?;
+        writeln!(f, "Fullmove number: {}", self.fm)?;
+        writeln!(f, "Key: {:016X}", self.hash)?;
+        writeln!(f, "Pawn key: {:016X}", self.pawn_hash)?;
+        writeln!(f, "Non-pawn keys: {:016X} {:016X}", self.non_pawn_hashes[White], self.non_pawn_hashes[Black])?;
+        writeln!(f, "Major key: {:016X}", self.major_hash)?;
+        writeln!(f, "Minor key: {:016X}", self.minor_hash)?;
+        write!(f, "Checkers: {}", if checkers.is_empty() { "-".to_string() } else { checkers.join(" ") })
+    }
+}
+
 pub enum Rights {
     None = 0b0000,
     WKS = 0b0001,
@@ -535,7 +712,152 @@ impl CastleTravel {
 #[cfg(test)]
 mod tests {
     use crate::board::Board;
+    use crate::movegen::{gen_legal_moves, is_check, MoveFilter};
     use crate::moves::{Move, MoveFlag};
+    use std::fs;
+
+    #[test]
+    fn test_gives_check_perft_suite() {
+        let perft_suite = fs::read_to_string("resources/perft_suite.epd").unwrap();
+        for line in perft_suite.lines() {
+            let fen = line.split(';').next().unwrap().trim();
+            assert_gives_check(&Board::from_fen(fen).unwrap(), 3);
+        }
+    }
+
+    #[test]
+    fn test_gives_check_special_moves() {
+        // (fen, move, gives check)
+        let cases = [
+            // Castling puts the rook on the king's file
+            ("5k2/8/8/8/8/8/8/4K2R w K - 0 1", "e1g1", true),
+            ("3k4/8/8/8/8/8/8/R3K3 w Q - 0 1", "e1c1", true),
+            // En passant discovers a check along the rank
+            ("8/8/8/k1pP3R/8/8/8/7K w - c6 0 1", "d5c6", true),
+            // En passant discovers a check along a diagonal through the captured pawn
+            ("8/8/2k5/8/3pP3/8/8/B5K1 b - e3 0 1", "d4e3", false),
+            ("7k/8/8/3pP3/8/8/8/B6K w - d6 0 1", "e5d6", true),
+            // Promotion checking back along the file the pawn left
+            ("8/1P6/8/8/8/8/1k6/7K w - - 0 1", "b7b8q", true),
+            ("8/1P6/8/8/8/8/1k6/7K w - - 0 1", "b7b8n", false),
+            // Discovered check by a knight, but not by a pawn pushed along the line to the king
+            ("4k3/8/8/8/4N3/8/8/4R1K1 w - - 0 1", "e4c3", true),
+            ("4k3/8/8/8/4P3/8/8/4R1K1 w - - 0 1", "e4e5", false),
+        ];
+        for (fen, uci, expected) in cases {
+            let board = Board::from_fen(fen).unwrap();
+            let mv = gen_legal_moves(&board, MoveFilter::All).iter()
+                .map(|entry| entry.mv)
+                .find(|mv| mv.to_uci() == uci)
+                .unwrap_or_else(|| panic!("{} is not legal in {}", uci, fen));
+            assert_eq!(board.gives_check(&mv), expected, "{} in {}", uci, fen);
+        }
+    }
+
+    #[test]
+    fn test_make_unmake_restores_board() {
+        let perft_suite = fs::read_to_string("resources/perft_suite.epd").unwrap();
+        let mut rng: u64 = 0x9E3779B97F4A7C15;
+        for line in perft_suite.lines() {
+            let fen = line.split(';').next().unwrap().trim();
+            let mut board = Board::from_fen(fen).unwrap();
+
+            // Walk a random game from each position, unmaking every move before playing it for real
+            for _ in 0..100 {
+                let moves = gen_legal_moves(&board, MoveFilter::All);
+                if moves.is_empty() {
+                    break;
+                }
+                rng ^= rng << 13;
+                rng ^= rng >> 7;
+                rng ^= rng << 17;
+                let mv = moves.list[rng as usize % moves.len].mv;
+
+                let before = board;
+                let undo = board.make(&mv);
+                board.unmake(&mv, undo);
+                assert_same_board(&board, &before, &format!("{} after {}", before.to_fen(), mv.to_uci()));
+
+                if before.checkers.is_empty() {
+                    let undo = board.make_null_move();
+                    board.unmake_null_move(undo);
+                    assert_same_board(&board, &before, &format!("{} after null move", before.to_fen()));
+                }
+
+                board.make(&mv);
+            }
+        }
+    }
+
+    fn assert_same_board(board: &Board, expected: &Board, context: &str) {
+        assert_eq!(board.bb, expected.bb, "bitboards: {}", context);
+        assert_eq!(board.pcs, expected.pcs, "pieces: {}", context);
+        assert_eq!(board.stm, expected.stm, "side to move: {}", context);
+        assert_eq!(board.hm, expected.hm, "halfmove clock: {}", context);
+        assert_eq!(board.fm, expected.fm, "fullmove number: {}", context);
+        assert_eq!(board.ep_sq.map(|sq| sq.0), expected.ep_sq.map(|sq| sq.0), "en passant: {}", context);
+        assert_eq!(board.castle, expected.castle, "castling rights: {}", context);
+        assert_eq!(board.hash, expected.hash, "hash: {}", context);
+        assert_eq!(board.pawn_hash, expected.pawn_hash, "pawn hash: {}", context);
+        assert_eq!(board.non_pawn_hashes, expected.non_pawn_hashes, "non-pawn hashes: {}", context);
+        assert_eq!(board.major_hash, expected.major_hash, "major hash: {}", context);
+        assert_eq!(board.minor_hash, expected.minor_hash, "minor hash: {}", context);
+        assert_eq!(board.checkers, expected.checkers, "checkers: {}", context);
+        assert_eq!(board.pinned, expected.pinned, "pinned: {}", context);
+        assert_eq!(board.discoverers, expected.discoverers, "discoverers: {}", context);
+        assert_eq!(board.check_squares, expected.check_squares, "check squares: {}", context);
+        // Catches any field added later but not listed above
+        assert!(board == expected, "{}", context);
+    }
+
+    #[test]
+    fn test_display() {
+        let board = Board::from_fen("rnb1kbnr/pppp1ppp/8/4p3/6Pq/5P2/PPPPP2P/RNBQKBNR w KQkq - 1 3").unwrap();
+        let display = board.to_string();
+        let lines: Vec<&str> = display.lines().collect();
+
+        assert_eq!(lines[0], " +---+---+---+---+---+---+---+---+");
+        assert_eq!(lines[1], " | r | n | b |   | k | b | n | r | 8");
+        assert_eq!(lines[9], " |   |   |   |   |   |   | P | q | 4");
+        assert_eq!(lines[15], " | R | N | B | Q | K | B | N | R | 1");
+        assert_eq!(lines[17], "   a   b   c   d   e   f   g   h");
+        assert!(lines.contains(&"Side to move: White"));
+        assert!(lines.contains(&"Castling: KQkq"));
+        assert!(lines.contains(&"En passant: -"));
+        assert!(lines.contains(&"Halfmove clock: 1"));
+        assert!(lines.contains(&"Fullmove number: 3"));
+        assert!(lines.contains(&format!("Key: {:016X}", board.hash).as_str()));
+        assert_eq!(lines.last(), Some(&"Checkers: h4"));
+    }
+
+    #[test]
+    fn test_check_info_is_updated() {
+        let mut board = Board::from_fen("4k3/8/8/8/1b6/8/3P4/4K3 w - - 0 1").unwrap();
+        assert!(board.checkers.is_empty());
+        assert!(board.pinned.contains(crate::types::square::Square(11)));
+
+        board.make(&Move::parse_uci("e1f2"));
+        assert!(board.pinned.is_empty());
+        board.make(&Move::parse_uci("b4c5"));
+        assert!(board.checkers.contains(crate::types::square::Square(34)));
+
+        board.make_null_move();
+        assert!(board.checkers.is_empty());
+        assert!(board.discoverers.is_empty());
+    }
+
+    fn assert_gives_check(board: &Board, depth: u8) {
+        assert_eq!(!board.checkers.is_empty(), is_check(board, board.stm), "{}", board.to_fen());
+        for entry in gen_legal_moves(board, MoveFilter::All).iter() {
+            let mut child = *board;
+            child.make(&entry.mv);
+            assert_eq!(board.gives_check(&entry.mv), is_check(&child, child.stm),
+                       "{} in {}", entry.mv.to_uci(), board.to_fen());
+            if depth > 1 {
+                assert_gives_check(&child, depth - 1);
+            }
+        }
+    }
 
     #[test]
     fn standard_move() {
@@ -601,17 +923,31 @@ mod tests {
     }
 
     #[test]
-    fn insufficient_material() {
-        assert!(Board::from_fen("8/1k6/2n5/8/8/5N2/6K1/8 w - - 0 1").is_insufficient_material());
-        assert!(!Board::from_fen("8/1k6/2np4/8/8/5N2/6K1/8 w - - 0 1").is_insufficient_material());
-        assert!(Board::from_fen("8/1k6/2b5/8/8/5B2/6K1/8 w - - 0 1").is_insufficient_material());
-        assert!(Board::from_fen("8/1k6/2b5/8/8/5N2/6K1/8 w - - 0 1").is_insufficient_material());
-        assert!(Board::from_fen("8/1k6/2bN4/8/8/5N2/6K1/8 w - - 0 1").is_insufficient_material());
-        assert!(!Board::from_fen("8/1k6/2bb4/8/8/8/6K1/8 w - - 0 1").is_insufficient_material());
+    fn pseudo_legal_pawn_moves() {
+        let board = Board::from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1").unwrap();
+        assert!(board.is_pseudo_legal(&Move::parse_uci_with_flag("e2e4", MoveFlag::DoublePush)));
+        assert!(board.is_pseudo_legal(&Move::parse_uci("e2e3")));
+
+        // Captures must be one rank forward, and cannot carry the double push flag
+        let board = Board::from_fen("rnbqkb1r/pppppppp/8/8/8/2n5/PPPPPPPP/RNBQKBNR w KQkq - 0 1").unwrap();
+        assert!(board.is_pseudo_legal(&Move::parse_uci("b2c3")));
+        assert!(!board.is_pseudo_legal(&Move::parse_uci_with_flag("b2c3", MoveFlag::DoublePush)));
+        let board = Board::from_fen("rnbqkb1r/pppppppp/8/8/2n5/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1").unwrap();
+        assert!(!board.is_pseudo_legal(&Move::parse_uci("b2c4")));
+
+        // En passant must target the en passant square
+        let board = Board::from_fen("rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3").unwrap();
+        assert!(board.is_pseudo_legal(&Move::parse_uci_with_flag("e5f6", MoveFlag::EnPassant)));
+        assert!(!board.is_pseudo_legal(&Move::parse_uci_with_flag("e5d6", MoveFlag::EnPassant)));
+
+        // Moves to the promotion rank must be promotions
+        let board = Board::from_fen("4k3/P7/8/8/8/8/8/4K3 w - - 0 1").unwrap();
+        assert!(board.is_pseudo_legal(&Move::parse_uci("a7a8q")));
+        assert!(!board.is_pseudo_legal(&Move::parse_uci("a7a8")));
     }
 
     fn assert_make_move(start_fen: &str, end_fen: &str, m: Move) {
-        let mut board = Board::from_fen(start_fen);
+        let mut board = Board::from_fen(start_fen).unwrap();
         board.make(&m);
         assert_eq!(board.to_fen(), end_fen);
     }
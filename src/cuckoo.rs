@@ -0,0 +1,122 @@
+use crate::attacks::{KING, KNIGHT};
+use crate::magics::{gen_bishop_attacks, gen_rook_attacks};
+use crate::moves::Move;
+use crate::zobrist::{PIECE_KEYS, SIDE_KEY};
+
+/// Cuckoo hash tables of every reversible non-pawn move, keyed by the change in Zobrist hash the
+/// move causes. This lets the search check whether the side to move can repeat an earlier position
+/// with a single move, without generating moves. See Marcel van Kervinck's paper on the idea.
+pub static CUCKOO: Cuckoo = Cuckoo::new();
+
+const SIZE: usize = 8192;
+
+pub struct Cuckoo {
+    keys: [u64; SIZE],
+    moves: [Move; SIZE],
+}
+
+impl Cuckoo {
+
+    const fn new() -> Self {
+        let mut cuckoo = Cuckoo { keys: [0; SIZE], moves: [Move::NONE; SIZE] };
+
+        // Piece keys are indexed by piece, then offset by 6 for black. Pawn moves are irreversible.
+        let mut pc_idx = 0;
+        while pc_idx < 12 {
+            if pc_idx % 6 == 0 {
+                pc_idx += 1;
+                continue;
+            }
+            let mut from = 0;
+            while from < 64 {
+                let mut to = from + 1;
+                while to < 64 {
+                    if empty_board_attacks(pc_idx % 6, from) & (1 << to) != 0 {
+                        let key = PIECE_KEYS[pc_idx][from] ^ PIECE_KEYS[pc_idx][to] ^ SIDE_KEY;
+                        let mv = Move((from | (to << 6)) as u16);
+                        cuckoo.insert(key, mv);
+                    }
+                    to += 1;
+                }
+                from += 1;
+            }
+            pc_idx += 1;
+        }
+
+        cuckoo
+    }
+
+    const fn insert(&mut self, mut key: u64, mut mv: Move) {
+        let mut idx = h1(key);
+        loop {
+            let displaced_key = self.keys[idx];
+            let displaced_move = self.moves[idx];
+            self.keys[idx] = key;
+            self.moves[idx] = mv;
+            if displaced_move.0 == Move::NONE.0 {
+                return;
+            }
+            key = displaced_key;
+            mv = displaced_move;
+            idx = if idx == h1(key) { h2(key) } else { h1(key) };
+        }
+    }
+
+    /// Find the reversible move that changes the hash by the given key, if there is one.
+    pub fn lookup(&self, key: u64) -> Option<Move> {
+        let idx = h1(key);
+        if self.keys[idx] == key {
+            return Some(self.moves[idx]);
+        }
+        let idx = h2(key);
+        if self.keys[idx] == key {
+            return Some(self.moves[idx]);
+        }
+        None
+    }
+
+}
+
+const fn empty_board_attacks(piece: usize, sq: usize) -> u64 {
+    match piece {
+        1 => KNIGHT[sq].0,
+        2 => gen_bishop_attacks(sq, 0),
+        3 => gen_rook_attacks(sq, 0),
+        4 => gen_bishop_attacks(sq, 0) | gen_rook_attacks(sq, 0),
+        _ => KING[sq].0,
+    }
+}
+
+const fn h1(key: u64) -> usize {
+    (key & 0x1FFF) as usize
+}
+
+const fn h2(key: u64) -> usize {
+    ((key >> 16) & 0x1FFF) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cuckoo::CUCKOO;
+    use crate::moves::Move;
+    use crate::zobrist::{PIECE_KEYS, SIDE_KEY};
+
+    #[test]
+    fn test_cuckoo_table_size() {
+        let count = CUCKOO.moves.iter().filter(|mv| mv.exists()).count();
+        assert_eq!(count, 3668);
+    }
+
+    #[test]
+    fn test_cuckoo_lookup() {
+        // White knight g1-f3
+        let key = PIECE_KEYS[1][6] ^ PIECE_KEYS[1][21] ^ SIDE_KEY;
+        let mv = CUCKOO.lookup(key).unwrap();
+        assert_eq!(mv, Move::parse_uci("g1f3"));
+
+        // Not a legal knight move
+        let key = PIECE_KEYS[1][6] ^ PIECE_KEYS[1][22] ^ SIDE_KEY;
+        assert!(CUCKOO.lookup(key).is_none());
+    }
+
+}
@@ -0,0 +1,175 @@
+use crate::board::Board;
+use crate::movegen::{gen_legal_moves, MoveFilter};
+use crate::types::bitboard::Bitboard;
+use crate::types::piece::Piece;
+
+const LIGHT_SQUARES: Bitboard = Bitboard(0x55AA55AA55AA55AA);
+
+/// Whether the position is drawn by the fifty-move rule, insufficient material or repetition.
+/// The keys are the hashes of every position in the game so far, ending with the current one.
+pub fn is_draw(board: &Board, keys: &[u64], root_ply: usize) -> bool {
+    is_fifty_move_draw(board) || is_insufficient_material(board) || is_repetition(board, keys, root_ply)
+}
+
+/// Whether fifty moves by each side have passed without a capture or pawn move. A checkmate
+/// delivered on the final move takes priority over the draw.
+pub fn is_fifty_move_draw(board: &Board) -> bool {
+    board.hm >= 100
+        && (board.checkers.is_empty() || !gen_legal_moves(board, MoveFilter::Evasions).is_empty())
+}
+
+/// Whether neither side can possibly checkmate, by any sequence of legal moves (a FIDE dead
+/// position). That is the case for a lone king against a king and at most one minor piece, and
+/// for any number of bishops when they all stand on squares of the same colour.
+pub fn is_insufficient_material(board: &Board) -> bool {
+    let pawns = board.pcs(Piece::Pawn);
+    let rooks = board.pcs(Piece::Rook);
+    let queens = board.pcs(Piece::Queen);
+    if !(pawns | rooks | queens).is_empty() {
+        return false;
+    }
+
+    let knights = board.pcs(Piece::Knight);
+    let bishops = board.pcs(Piece::Bishop);
+    if (knights | bishops).count() <= 1 {
+        return true;
+    }
+
+    knights.is_empty() && ((bishops & LIGHT_SQUARES).is_empty() || (bishops & !LIGHT_SQUARES).is_empty())
+}
+
+/// Whether the current position has occurred before since the last irreversible move. Once is
+/// enough within the search tree, but positions before the root must have occurred twice, so that
+/// the game is really drawn rather than only heading for a draw.
+pub fn is_repetition(board: &Board, keys: &[u64], root_ply: usize) -> bool {
+    let curr_hash = board.hash;
+    let mut repetitions = 0;
+    let end = keys.len().saturating_sub(board.hm as usize + 1);
+    for ply in (end..keys.len().saturating_sub(2)).rev() {
+        let hash = keys[ply];
+        repetitions += u8::from(curr_hash == hash);
+
+        // Two-fold repetition of positions within the search tree
+        if repetitions == 1 && ply >= root_ply {
+            return true;
+        }
+
+        // Three-fold repetition including positions before search root
+        if repetitions == 2 {
+            return true;
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::board::Board;
+    use crate::draw::{is_draw, is_fifty_move_draw, is_insufficient_material, is_repetition};
+    use crate::fen::STARTPOS;
+    use crate::movegen::{gen_legal_moves, MoveFilter};
+
+    #[test]
+    fn test_insufficient_material() {
+        // (fen, dead position)
+        let cases = [
+            ("8/7k/8/8/8/8/6K1/8 w - - 0 1", true),
+            ("8/7k/2n5/8/8/8/6K1/8 w - - 0 1", true),
+            ("8/7k/8/8/8/5B2/6K1/8 w - - 0 1", true),
+            // Bishops on squares of the same colour, on one or both sides
+            ("8/7k/2b5/8/8/5B2/6K1/8 w - - 0 1", true),
+            ("8/7k/8/8/8/2B2B2/6K1/8 w - - 0 1", false),
+            ("8/7k/8/8/3B4/2B2B2/6K1/8 w - - 0 1", false),
+            ("8/7k/8/8/8/5B2/4B1K1/8 w - - 0 1", true),
+            ("8/7k/b1b5/8/8/5B2/4B1K1/8 w - - 0 1", true),
+            // Bishops on squares of different colours
+            ("8/7k/2bb4/8/8/8/6K1/8 w - - 0 1", false),
+            ("8/7k/3b4/8/8/5B2/6K1/8 w - - 0 1", false),
+            // A mate is possible with knights, or a knight and a bishop, on the board
+            ("8/7k/2n5/8/8/5N2/6K1/8 w - - 0 1", false),
+            ("8/7k/2b5/8/8/5N2/6K1/8 w - - 0 1", false),
+            ("8/7k/8/8/8/4NN2/6K1/8 w - - 0 1", false),
+            ("8/7k/2bN4/8/8/5N2/6K1/8 b - - 0 1", false),
+            // Any pawn, rook or queen
+            ("8/7k/2np4/8/8/5N2/6K1/8 w - - 0 1", false),
+            ("8/1k6/8/8/8/8/6K1/7R w - - 0 1", false),
+            ("8/1k6/8/8/8/8/6K1/7q w - - 0 1", false),
+            ("8/7k/8/8/8/8/P5K1/8 w - - 0 1", false),
+        ];
+        for (fen, expected) in cases {
+            let board = Board::from_fen(fen).unwrap();
+            assert_eq!(is_insufficient_material(&board), expected, "{}", fen);
+        }
+    }
+
+    #[test]
+    fn test_fifty_move_rule() {
+        // (fen, drawn by the fifty-move rule)
+        let cases = [
+            ("8/8/8/8/8/2k5/1r6/K7 w - - 99 80", false),
+            ("8/8/8/8/8/2k5/1r6/K7 w - - 100 80", true),
+            ("8/8/8/8/8/2k5/1r6/K7 w - - 150 80", true),
+            // Checkmate on the final move wins rather than draws
+            ("8/8/8/8/8/1k6/8/K1r5 w - - 100 80", false),
+            // A check that can be escaped is still drawn
+            ("8/8/8/8/8/2k5/8/K1r5 w - - 100 80", true),
+            // So is stalemate
+            ("8/8/8/4k3/8/8/2q5/K7 w - - 100 80", true),
+        ];
+        for (fen, expected) in cases {
+            let board = Board::from_fen(fen).unwrap();
+            assert_eq!(is_fifty_move_draw(&board), expected, "{}", fen);
+        }
+    }
+
+    #[test]
+    fn test_repetition() {
+        // (moves from the start position, root ply, repetition)
+        let cases = [
+            ("g1f3 g8f6 f3g1", 0, false),
+            // Twice within the search tree
+            ("g1f3 g8f6 f3g1 f6g8", 0, true),
+            ("g1f3 g8f6 f3g1 f6g8 g1f3", 1, true),
+            // Twice, but the first time was before the root
+            ("g1f3 g8f6 f3g1 f6g8", 1, false),
+            ("g1f3 g8f6 f3g1 f6g8", 4, false),
+            // Three times, before the root
+            ("g1f3 g8f6 f3g1 f6g8 g1f3 g8f6 f3g1 f6g8", 8, true),
+            // A pawn move in between resets the count
+            ("g1f3 g8f6 f3g1 f6g8 e2e3 e7e6 g1f3 g8f6 f3g1 f6g8", 10, false),
+            ("g1f3 g8f6 f3g1 f6g8 e2e3 e7e6 g1f3 g8f6 f3g1 f6g8", 6, true),
+        ];
+        for (moves, root_ply, expected) in cases {
+            let (board, keys) = play(moves);
+            assert_eq!(is_repetition(&board, &keys, root_ply), expected, "{} from ply {}", moves, root_ply);
+        }
+    }
+
+    #[test]
+    fn test_is_draw() {
+        let (board, keys) = play("g1f3 g8f6 f3g1 f6g8");
+        assert!(is_draw(&board, &keys, 0));
+        assert!(!is_draw(&board, &keys, 4));
+
+        let board = Board::from_fen("8/1k6/2b5/8/8/5B2/6K1/8 w - - 0 1").unwrap();
+        assert!(is_draw(&board, &[board.hash], 0));
+
+        let board = Board::from_fen("8/8/8/8/8/1k6/8/K1r5 w - - 100 80").unwrap();
+        assert!(!is_draw(&board, &[board.hash], 0));
+    }
+
+    fn play(moves: &str) -> (Board, Vec<u64>) {
+        let mut board = Board::from_fen(STARTPOS).unwrap();
+        let mut keys = vec![board.hash];
+        for uci in moves.split_whitespace() {
+            let mv = gen_legal_moves(&board, MoveFilter::All).iter()
+                .map(|entry| entry.mv)
+                .find(|mv| mv.to_uci() == uci)
+                .unwrap();
+            board.make(&mv);
+            keys.push(board.hash);
+        }
+        (board, keys)
+    }
+
+}
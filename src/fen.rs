@@ -1,21 +1,73 @@
-use crate::board::Board;
+use crate::board::{Board, Rights};
+use crate::movegen::is_check;
 use crate::types::piece::Piece;
 use crate::types::side::Side;
 use crate::types::side::Side::{Black, White};
 use crate::types::square::Square;
 use crate::types::{File, Rank};
 use crate::zobrist::Zobrist;
+use std::fmt;
 
 pub const STARTPOS: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
 
+/// Why a FEN string could not be turned into a board, either because it is malformed or because
+/// it describes a position that cannot arise in a game.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FenError {
+    MissingField(&'static str),
+    TooManyFields(usize),
+    WrongRankCount(usize),
+    WrongRankLength(String),
+    InvalidPiece(char),
+    InvalidSideToMove(String),
+    InvalidCastlingRights(String),
+    InvalidEnPassant(String),
+    InvalidHalfmoveClock(String),
+    InvalidFullmoveNumber(String),
+    WrongKingCount(Side, u32),
+    PawnOnBackRank,
+    InconsistentEnPassant(String),
+    InconsistentCastlingRights(char),
+    OpponentInCheck,
+}
+
+impl fmt::Display for FenError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FenError::MissingField(field) => write!(f, "missing {}", field),
+            FenError::TooManyFields(count) => write!(f, "expected at most 6 fields, found {}", count),
+            FenError::WrongRankCount(count) => write!(f, "expected 8 ranks, found {}", count),
+            FenError::WrongRankLength(rank) => write!(f, "rank '{}' does not have 8 squares", rank),
+            FenError::InvalidPiece(ch) => write!(f, "invalid piece '{}'", ch),
+            FenError::InvalidSideToMove(stm) => write!(f, "invalid side to move '{}'", stm),
+            FenError::InvalidCastlingRights(rights) => write!(f, "invalid castling rights '{}'", rights),
+            FenError::InvalidEnPassant(sq) => write!(f, "invalid en passant square '{}'", sq),
+            FenError::InvalidHalfmoveClock(hm) => write!(f, "invalid halfmove clock '{}'", hm),
+            FenError::InvalidFullmoveNumber(fm) => write!(f, "invalid fullmove number '{}'", fm),
+            FenError::WrongKingCount(side, count) => write!(f, "{:?} has {} kings", side, count),
+            FenError::PawnOnBackRank => write!(f, "pawn on the first or eighth rank"),
+            FenError::InconsistentEnPassant(sq) => write!(f, "no double pawn push could have left en passant square '{}'", sq),
+            FenError::InconsistentCastlingRights(right) => write!(f, "castling right '{}' without king and rook on their squares", right),
+            FenError::OpponentInCheck => write!(f, "side not to move is in check"),
+        }
+    }
+}
+
+impl std::error::Error for FenError {}
+
 impl Board {
-    pub fn from_fen(fen: &str) -> Board {
+    pub fn from_fen(fen: &str) -> Result<Board, FenError> {
         let mut board = Board::empty();
         let parts: Vec<&str> = fen.split_whitespace().collect();
 
-        let rows: Vec<&str> = parts[0].split('/').collect();
+        if parts.len() > 6 {
+            return Err(FenError::TooManyFields(parts.len()));
+        }
+
+        let placement = parts.first().ok_or(FenError::MissingField("piece placement"))?;
+        let rows: Vec<&str> = placement.split('/').collect();
         if rows.len() != 8 {
-            panic!("Invalid FEN string");
+            return Err(FenError::WrongRankCount(rows.len()));
         }
 
         for (rank, row) in rows.iter().enumerate() {
@@ -26,28 +78,46 @@ impl Board {
                         file += ch.to_digit(10).unwrap() as usize;
                     }
                     'P' | 'N' | 'B' | 'R' | 'Q' | 'K' | 'p' | 'n' | 'b' | 'r' | 'q' | 'k' => {
+                        if file >= 8 {
+                            return Err(FenError::WrongRankLength(row.to_string()));
+                        }
                         let sq = Square::from(File::parse(file), Rank::parse(7 - rank));
                         let piece = parse_piece(ch);
                         let side = if ch.is_uppercase() { White } else { Black };
                         board.toggle_sq(sq, piece, side);
                         file += 1;
                     }
-                    _ => panic!("Invalid character in FEN string"),
+                    _ => return Err(FenError::InvalidPiece(ch)),
                 }
             }
+            if file != 8 {
+                return Err(FenError::WrongRankLength(row.to_string()));
+            }
         }
 
-        board.stm = parse_stm(parts[1]);
-        board.castle = parse_castle_rights(parts[2]);
-        board.ep_sq = parse_ep_sq(parts[3]);
-        board.hm = parts.get(4).unwrap_or(&"0").parse().unwrap_or(0);
-        board.fm = parts.get(5).unwrap_or(&"0").parse().unwrap_or(0);
+        board.stm = parse_stm(parts.get(1).ok_or(FenError::MissingField("side to move"))?)?;
+        board.castle = parse_castle_rights(parts.get(2).ok_or(FenError::MissingField("castling rights"))?)?;
+        board.ep_sq = parse_ep_sq(parts.get(3).ok_or(FenError::MissingField("en passant square"))?)?;
+        board.hm = match parts.get(4) {
+            Some(hm) => hm.parse().map_err(|_| FenError::InvalidHalfmoveClock(hm.to_string()))?,
+            None => 0,
+        };
+        board.fm = match parts.get(5) {
+            // The counter saturates rather than rejecting very long games
+            Some(fm) => fm.parse::<u32>().map_err(|_| FenError::InvalidFullmoveNumber(fm.to_string()))?
+                .min(u8::MAX as u32) as u8,
+            None => 0,
+        };
+
+        validate(&board)?;
+
         board.hash = Zobrist::get_hash(&board);
         board.pawn_hash = Zobrist::get_pawn_hash(&board);
         board.non_pawn_hashes = Zobrist::get_non_pawn_hashes(&board);
         board.major_hash = Zobrist::get_major_hash(&board);
         board.minor_hash = Zobrist::get_minor_hash(&board);
-        board
+        board.update_check_info();
+        Ok(board)
     }
 
     pub fn to_fen(self) -> String {
@@ -117,7 +187,59 @@ impl Board {
     }
 }
 
-fn parse_castle_rights(castle: &str) -> u8 {
+/// Reject positions that could not have been reached by legal play from the start position, or
+/// that the rest of the engine cannot handle (e.g. a missing king).
+fn validate(board: &Board) -> Result<(), FenError> {
+    for side in [White, Black] {
+        let kings = board.king(side).count();
+        if kings != 1 {
+            return Err(FenError::WrongKingCount(side, kings));
+        }
+    }
+
+    if !(board.pcs(Piece::Pawn) & (Rank::One.to_bb() | Rank::Eight.to_bb())).is_empty() {
+        return Err(FenError::PawnOnBackRank);
+    }
+
+    if let Some(ep_sq) = board.ep_sq {
+        // The square must lie behind a pawn of theirs that has just moved two squares forward
+        let (ep_rank, pawn_sq, start_sq) = if board.stm == White {
+            (Rank::Six, ep_sq.minus(8), ep_sq.plus(8))
+        } else {
+            (Rank::Three, ep_sq.plus(8), ep_sq.minus(8))
+        };
+        let them = board.stm.flip();
+        if ep_sq.rank() != ep_rank
+            || !board.pawns(them).contains(pawn_sq)
+            || board.occ().contains(ep_sq)
+            || board.occ().contains(start_sq) {
+            return Err(FenError::InconsistentEnPassant(square_to_string(ep_sq)));
+        }
+    }
+
+    for (right, ch, side, king_sq, rook_sq) in [
+        (Rights::WKS, 'K', White, Square(4), Square(7)),
+        (Rights::WQS, 'Q', White, Square(4), Square(0)),
+        (Rights::BKS, 'k', Black, Square(60), Square(63)),
+        (Rights::BQS, 'q', Black, Square(60), Square(56)),
+    ] {
+        if board.castle & right as u8 != 0
+            && (!board.king(side).contains(king_sq) || !board.rooks(side).contains(rook_sq)) {
+            return Err(FenError::InconsistentCastlingRights(ch));
+        }
+    }
+
+    if is_check(board, board.stm.flip()) {
+        return Err(FenError::OpponentInCheck);
+    }
+
+    Ok(())
+}
+
+fn parse_castle_rights(castle: &str) -> Result<u8, FenError> {
+    if castle == "-" {
+        return Ok(0);
+    }
     let mut rights = 0;
     for c in castle.chars() {
         match c {
@@ -125,26 +247,27 @@ fn parse_castle_rights(castle: &str) -> u8 {
             'Q' => rights |= 0b0010,
             'k' => rights |= 0b0100,
             'q' => rights |= 0b1000,
-            '-' => (),
-            _ => panic!("Invalid character in castle rights"),
+            _ => return Err(FenError::InvalidCastlingRights(castle.to_string())),
         }
     }
-    rights
+    Ok(rights)
 }
 
-fn parse_ep_sq(ep_sq: &str) -> Option<Square> {
+fn parse_ep_sq(ep_sq: &str) -> Result<Option<Square>, FenError> {
     if ep_sq == "-" {
-        None
+        Ok(None)
     } else {
-        Some(parse_square(ep_sq))
+        parse_square(ep_sq)
+            .map(Some)
+            .ok_or_else(|| FenError::InvalidEnPassant(ep_sq.to_string()))
     }
 }
 
-fn parse_stm(stm: &str) -> Side {
+fn parse_stm(stm: &str) -> Result<Side, FenError> {
     match stm {
-        "w" => White,
-        "b" => Black,
-        _ => panic!("Invalid side to move in FEN string"),
+        "w" => Ok(White),
+        "b" => Ok(Black),
+        _ => Err(FenError::InvalidSideToMove(stm.to_string())),
     }
 }
 
@@ -160,13 +283,21 @@ fn parse_piece(c: char) -> Piece {
     }
 }
 
-fn parse_square(s: &str) -> Square {
-    let file = s.chars().next().unwrap() as usize - 'a' as usize;
-    let rank = s.chars().nth(1).unwrap() as usize - '1' as usize;
-    Square::from(File::parse(file), Rank::parse(rank))
+fn parse_square(s: &str) -> Option<Square> {
+    let mut chars = s.chars();
+    let file = File::from_char(chars.next()?)?;
+    let rank = Rank::from_char(chars.next()?)?;
+    if chars.next().is_some() {
+        return None;
+    }
+    Some(Square::from(file, rank))
+}
+
+pub(crate) fn square_to_string(sq: Square) -> String {
+    format!("{}{}", sq.file().to_char(), sq.rank().to_char())
 }
 
-fn piece_to_char(piece: Piece, side: Side) -> char {
+pub(crate) fn piece_to_char(piece: Piece, side: Side) -> char {
     let ch = match piece {
         Piece::Pawn => 'p',
         Piece::Knight => 'n',
@@ -181,3 +312,65 @@ fn piece_to_char(piece: Piece, side: Side) -> char {
         ch
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::board::Board;
+    use crate::fen::{FenError, STARTPOS};
+    use crate::types::side::Side::{Black, White};
+
+    #[test]
+    fn test_valid_fens() {
+        let fens = [
+            STARTPOS,
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+            "4k3/8/8/8/3pP3/8/8/4K3 b - e3 0 1",
+        ];
+        for fen in fens {
+            assert_eq!(Board::from_fen(fen).unwrap().to_fen(), fen);
+        }
+
+        // The move counters are optional
+        let board = Board::from_fen("4k3/8/8/8/8/8/8/4K3 w - -").unwrap();
+        assert_eq!((board.hm, board.fm), (0, 0));
+    }
+
+    #[test]
+    fn test_invalid_fens() {
+        let cases = [
+            ("", FenError::MissingField("piece placement")),
+            ("4k3/8/8/8/8/8/8/4K3", FenError::MissingField("side to move")),
+            ("4k3/8/8/8/8/8/8/4K3 w", FenError::MissingField("castling rights")),
+            ("4k3/8/8/8/8/8/8/4K3 w -", FenError::MissingField("en passant square")),
+            ("4k3/8/8/8/8/8/8/4K3 w - - 0 1 e2e4", FenError::TooManyFields(7)),
+            ("4k3/8/8/8/8/8/4K3 w - - 0 1", FenError::WrongRankCount(7)),
+            ("4k3/8/8/8/8/8/9/4K3 w - - 0 1", FenError::InvalidPiece('9')),
+            ("4k3/8/8/8/8/8/7/4K3 w - - 0 1", FenError::WrongRankLength("7".to_string())),
+            ("4k3/8/8/8/8/8/8p/4K3 w - - 0 1", FenError::WrongRankLength("8p".to_string())),
+            ("4k3/8/8/8/8/8/8/4X3 w - - 0 1", FenError::InvalidPiece('X')),
+            ("4k3/8/8/8/8/8/8/4K3 x - - 0 1", FenError::InvalidSideToMove("x".to_string())),
+            ("4k3/8/8/8/8/8/8/4K3 w KX - 0 1", FenError::InvalidCastlingRights("KX".to_string())),
+            ("4k3/8/8/8/8/8/8/4K3 w - e9 0 1", FenError::InvalidEnPassant("e9".to_string())),
+            ("4k3/8/8/8/8/8/8/4K3 w - - x 1", FenError::InvalidHalfmoveClock("x".to_string())),
+            ("4k3/8/8/8/8/8/8/4K3 w - - 0 x", FenError::InvalidFullmoveNumber("x".to_string())),
+            // Impossible positions
+            ("4k3/8/8/8/8/8/8/8 w - - 0 1", FenError::WrongKingCount(White, 0)),
+            ("4k3/8/8/8/8/8/8/3KK3 w - - 0 1", FenError::WrongKingCount(White, 2)),
+            ("8/8/8/8/8/8/8/4K3 w - - 0 1", FenError::WrongKingCount(Black, 0)),
+            ("4k2P/8/8/8/8/8/8/4K3 w - - 0 1", FenError::PawnOnBackRank),
+            ("4k3/8/8/8/8/8/8/p3K3 w - - 0 1", FenError::PawnOnBackRank),
+            ("4k3/8/8/3pP3/8/8/8/4K3 w - e6 0 1", FenError::InconsistentEnPassant("e6".to_string())),
+            ("4k3/8/8/3pP3/8/8/8/4K3 w - d3 0 1", FenError::InconsistentEnPassant("d3".to_string())),
+            ("4k3/3p4/8/3pP3/8/8/8/4K3 w - d6 0 1", FenError::InconsistentEnPassant("d6".to_string())),
+            ("4k3/8/8/8/8/8/8/4K3 w K - 0 1", FenError::InconsistentCastlingRights('K')),
+            ("4k3/8/8/8/8/8/8/R4K2 w Q - 0 1", FenError::InconsistentCastlingRights('Q')),
+            ("r3k3/8/8/8/8/8/8/4K3 w kq - 0 1", FenError::InconsistentCastlingRights('k')),
+            ("4k3/8/8/8/8/8/8/4R1K1 w - - 0 1", FenError::OpponentInCheck),
+        ];
+        for (fen, expected) in cases {
+            assert_eq!(Board::from_fen(fen).err(), Some(expected), "{}", fen);
+        }
+    }
+
+}
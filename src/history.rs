@@ -23,6 +23,12 @@ pub struct CaptureHistory {
     entries: Box<[PieceToHistory<[i16; 6]>; 2]>,
 }
 
+/// The quiet move that last refuted each move, indexed by the side making the refutation, then the
+/// piece and destination of the move being refuted.
+pub struct CounterMoveTable {
+    entries: Box<[PieceToHistory<Move>; 2]>,
+}
+
 pub struct ThreatIndex {
     pub from_attacked: bool,
     pub to_attacked: bool
@@ -71,7 +77,7 @@ impl ContinuationHistory {
     }
 
     pub fn clear(&mut self) {
-        self.entries = Box::new([[[[0; 64]; 6]; 64]; 6])
+        *self = Self::new()
     }
 
 }
@@ -103,7 +109,7 @@ impl QuietHistory {
     }
 
     pub fn clear(&mut self) {
-        self.entries = Box::new([[[[[0; 64]; 64]; 2]; 2]; 2]);
+        *self = Self::new();
     }
 }
 
@@ -138,7 +144,7 @@ impl CorrectionHistory {
     }
 
     pub fn clear(&mut self) {
-        self.entries = Box::new([[0; Self::SIZE]; 2]);
+        *self = Self::new();
     }
 
     fn index(&self, key: u64) -> usize {
@@ -154,6 +160,12 @@ impl Default for CorrectionHistory {
     }
 }
 
+impl Default for CaptureHistory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl CaptureHistory {
 
     const MAX: i16 = 16384;
@@ -174,7 +186,35 @@ impl CaptureHistory {
     }
 
     pub fn clear(&mut self) {
-        self.entries = Box::new([[[[0; 6]; 64]; 6], [[[0; 6]; 64]; 6]]);
+        *self = Self::new();
+    }
+
+}
+
+impl Default for CounterMoveTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CounterMoveTable {
+
+    pub fn new() -> Self {
+        CounterMoveTable {
+            entries: Box::new([[[Move::NONE; 64]; 6]; 2]),
+        }
+    }
+
+    pub fn get(&self, stm: Side, prev_mv: Move, prev_pc: Piece) -> Move {
+        self.entries[stm][prev_pc][prev_mv.to()]
+    }
+
+    pub fn update(&mut self, stm: Side, prev_mv: &Move, prev_pc: Piece, mv: &Move) {
+        self.entries[stm][prev_pc][prev_mv.to()] = *mv;
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::new();
     }
 
 }
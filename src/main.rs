@@ -3,6 +3,8 @@ use crate::uci::UCI;
 pub mod attacks;
 pub mod bench;
 pub mod board;
+pub mod cuckoo;
+pub mod draw;
 pub mod fen;
 pub mod magics;
 pub mod movegen;
@@ -17,6 +19,8 @@ pub mod history;
 pub mod see;
 pub mod types;
 pub mod network;
+pub mod parameters;
+pub mod skill;
 mod time;
 mod movepicker;
 
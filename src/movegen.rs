@@ -14,10 +14,17 @@ pub enum MoveFilter {
     All,
     Quiets,
     Noisies,
-    Captures
+    Captures,
+    /// Moves that get the king out of check: king moves, captures of the checker and
+    /// interpositions. These are always generated legally. Outside of check this is every legal move.
+    Evasions
 }
 
 pub fn gen_moves(board: &Board, filter: MoveFilter) -> MoveList {
+    if filter == MoveFilter::Evasions {
+        return gen_legal_moves(board, filter);
+    }
+
     let side = board.stm;
     let mut moves = MoveList::new();
 
@@ -26,7 +33,8 @@ pub fn gen_moves(board: &Board, filter: MoveFilter) -> MoveList {
     let occ = us | them;
 
     // handle special moves first (en passant, promo, castling etc.)
-    gen_pawn_moves(board, side, occ, them, filter, &mut moves);
+    let pawns = board.pcs(Piece::Pawn) & us;
+    gen_pawn_moves(board, side, pawns, occ, them, Bitboard::ALL, None, filter, &mut moves);
     if filter != MoveFilter::Captures && filter != MoveFilter::Noisies {
         gen_castle_moves(board, side, &mut moves);
     }
@@ -35,7 +43,8 @@ pub fn gen_moves(board: &Board, filter: MoveFilter) -> MoveList {
         MoveFilter::All => Bitboard::ALL,
         MoveFilter::Quiets => !them,
         MoveFilter::Noisies => them,
-        MoveFilter::Captures => them
+        MoveFilter::Captures => them,
+        MoveFilter::Evasions => Bitboard::ALL
     };
 
     // handle standard moves
@@ -51,24 +60,144 @@ pub fn gen_moves(board: &Board, filter: MoveFilter) -> MoveList {
     moves
 }
 
-#[inline(always)]
-fn gen_pawn_moves(board: &Board, side: Side, occ: Bitboard, them: Bitboard, filter: MoveFilter, moves: &mut MoveList) {
+/// Generate only the legal moves, using the same filters as [`gen_moves`]. Checkers and pins are
+/// computed once up front, so no move needs to be made to test its legality.
+pub fn gen_legal_moves(board: &Board, filter: MoveFilter) -> MoveList {
+    let side = board.stm;
+    let mut moves = MoveList::new();
+
+    let us = board.us();
+    let them = board.them();
+    let occ = us | them;
+    let legality = Legality::new(board);
+
+    let filter_mask = match filter {
+        MoveFilter::All => Bitboard::ALL,
+        MoveFilter::Quiets => !them,
+        MoveFilter::Noisies => them,
+        MoveFilter::Captures => them,
+        MoveFilter::Evasions => Bitboard::ALL
+    };
 
-    let pawns = board.pcs(Piece::Pawn) & board.side(side);
+    // In double check only the king can move
+    if legality.checkers.count() < 2 {
+        let pawns = board.pcs(Piece::Pawn) & us;
+        gen_pawn_moves(board, side, pawns & !legality.pinned, occ, them, legality.checkmask, Some(&legality), filter, &mut moves);
+        for from in pawns & legality.pinned {
+            let mask = legality.checkmask & legality.pin_ray(from);
+            gen_pawn_moves(board, side, Bitboard::of_sq(from), occ, them, mask, Some(&legality), filter, &mut moves);
+        }
+
+        if filter != MoveFilter::Captures && filter != MoveFilter::Noisies && legality.checkers.is_empty() {
+            gen_castle_moves(board, side, &mut moves);
+        }
+
+        for &pc in [Piece::Knight, Piece::Bishop, Piece::Rook, Piece::Queen].iter() {
+            for from in board.pcs(pc) & us {
+                let mask = !us & filter_mask & legality.checkmask & legality.pin_ray(from);
+                for to in attacks::attacks(from, pc, side, occ) & mask {
+                    moves.add_move(from, to, MoveFlag::Standard);
+                }
+            }
+        }
+    }
+
+    // The king must not step onto an attacked square, including squares behind it on a checking ray
+    let king_sq = board.king_sq(side);
+    let occ_without_king = occ ^ Bitboard::of_sq(king_sq);
+    for to in attacks::king(king_sq) & !us & filter_mask {
+        if !is_sq_attacked(to, side, occ_without_king, board) {
+            moves.add_move(king_sq, to, MoveFlag::Standard);
+        }
+    }
+
+    moves
+}
+
+/// The pieces giving check and the pins against the king of the side to move.
+struct Legality {
+    king_sq: Square,
+    checkers: Bitboard,
+    /// The squares a non-king move must land on to resolve a check: the checker itself or the
+    /// squares between it and the king. All squares when not in check.
+    checkmask: Bitboard,
+    pinned: Bitboard,
+    orthogonals: Bitboard,
+    diagonals: Bitboard,
+}
+
+impl Legality {
+
+    fn new(board: &Board) -> Self {
+        let side = board.stm;
+        let king_sq = board.king_sq(side);
+        let checkers = board.checkers;
+        let checkmask = match checkers.count() {
+            0 => Bitboard::ALL,
+            1 => attacks::between(king_sq, checkers.lsb()) | checkers,
+            _ => Bitboard::empty(),
+        };
+        Legality {
+            king_sq,
+            checkers,
+            checkmask,
+            pinned: board.pinned,
+            orthogonals: board.rooks(side.flip()) | board.queens(side.flip()),
+            diagonals: board.bishops(side.flip()) | board.queens(side.flip()),
+        }
+    }
+
+    /// A pinned piece may only move along the line through its king and the pinner.
+    #[inline(always)]
+    fn pin_ray(&self, sq: Square) -> Bitboard {
+        if self.pinned.contains(sq) {
+            attacks::line(self.king_sq, sq)
+        } else {
+            Bitboard::ALL
+        }
+    }
+
+    /// En passant removes two pawns from the same rank at once, so rather than relying on the pin
+    /// masks, check directly that no slider sees the king once both pawns are gone.
+    fn is_legal_en_passant(&self, board: &Board, from: Square, to: Square) -> bool {
+        let captured = Square(to.0 ^ 8);
+        if !self.checkmask.contains(to) && !self.checkmask.contains(captured) {
+            return false;
+        }
+        let occ = board.occ() ^ Bitboard::of_sq(from) ^ Bitboard::of_sq(captured) | Bitboard::of_sq(to);
+        (attacks::rook(self.king_sq, occ) & self.orthogonals).is_empty()
+            && (attacks::bishop(self.king_sq, occ) & self.diagonals).is_empty()
+    }
+
+}
+
+/// Generate pawn moves landing on the given mask. If legality info is given, en passant captures
+/// are only generated when legal, since they are not restricted by the mask.
+#[inline(always)]
+#[allow(clippy::too_many_arguments)]
+fn gen_pawn_moves(board: &Board,
+                  side: Side,
+                  pawns: Bitboard,
+                  occ: Bitboard,
+                  them: Bitboard,
+                  mask: Bitboard,
+                  legality: Option<&Legality>,
+                  filter: MoveFilter,
+                  moves: &mut MoveList) {
 
     if filter != MoveFilter::Captures && filter != MoveFilter::Noisies {
 
-        for to in single_push(pawns, side, occ) {
+        for to in single_push(pawns, side, occ) & mask {
             let from = if side == White { to.minus(8) } else { to.plus(8) };
             moves.add_move(from, to, MoveFlag::Standard);
         }
 
-        for to in double_push(pawns, side, occ) {
+        for to in double_push(pawns, side, occ) & mask {
             let from = if side == White { to.minus(16) } else { to.plus(16) };
             moves.add_move(from, to, MoveFlag::DoublePush);
         }
 
-        for to in push_promos(pawns, side, occ) {
+        for to in push_promos(pawns, side, occ) & mask {
             let from = if side == White { to.minus(8) } else { to.plus(8) };
             add_promos(moves, from, to);
         }
@@ -76,12 +205,12 @@ fn gen_pawn_moves(board: &Board, side: Side, occ: Bitboard, them: Bitboard, filt
     }
 
     if filter != Quiets {
-        for to in left_capture(pawns, side, them) {
+        for to in left_capture(pawns, side, them) & mask {
             let from = if side == White { to.minus(7) } else { to.plus(9) };
             moves.add_move(from, to, MoveFlag::Standard);
         }
 
-        for to in right_capture(pawns, side, them) {
+        for to in right_capture(pawns, side, them) & mask {
             let from = if side == White { to.minus(9) } else { to.plus(7) };
             moves.add_move(from, to, MoveFlag::Standard);
         }
@@ -91,22 +220,26 @@ fn gen_pawn_moves(board: &Board, side: Side, occ: Bitboard, them: Bitboard, filt
 
             for to in left_capture(pawns, side, ep_bb) {
                 let from = if side == White { to.minus(7) } else { to.plus(9) };
-                moves.add_move(from, to, MoveFlag::EnPassant);
+                if legality.is_none_or(|l| l.is_legal_en_passant(board, from, to)) {
+                    moves.add_move(from, to, MoveFlag::EnPassant);
+                }
             }
 
             for to in right_capture(pawns, side, ep_bb) {
                 let from = if side == White { to.minus(9) } else { to.plus(7) };
-                moves.add_move(from, to, MoveFlag::EnPassant);
+                if legality.is_none_or(|l| l.is_legal_en_passant(board, from, to)) {
+                    moves.add_move(from, to, MoveFlag::EnPassant);
+                }
             }
 
         }
 
-        for to in left_capture_promos(pawns, side, them) {
+        for to in left_capture_promos(pawns, side, them) & mask {
             let from = if side == White { to.minus(7) } else { to.plus(9) };
             add_promos(moves, from, to);
         }
 
-        for to in right_capture_promos(pawns, side, them) {
+        for to in right_capture_promos(pawns, side, them) & mask {
             let from = if side == White { to.minus(9) } else { to.plus(7) };
             add_promos(moves, from, to);
         }
@@ -261,4 +394,95 @@ pub fn is_check(board: &Board, side: Side) -> bool {
     let occ = board.occ();
     let king_sq = board.king_sq(side);
     is_sq_attacked(king_sq, side, occ, board)
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use crate::board::Board;
+    use crate::movegen::{gen_legal_moves, gen_moves, is_check, MoveFilter};
+    use crate::moves::Move;
+    use std::fs;
+
+    const FILTERS: [MoveFilter; 4] = [MoveFilter::All, MoveFilter::Quiets, MoveFilter::Noisies, MoveFilter::Captures];
+
+    #[test]
+    fn test_evasions() {
+        // (fen, number of evasions)
+        let positions = [
+            // Single check from a rook: king moves, capturing the rook and blocking
+            ("4k3/8/8/8/8/2n5/8/4R1K1 b - - 0 1", 6),
+            // Double check: only king moves
+            ("4k3/8/3N4/8/8/8/8/4R1K1 b - - 0 1", 3),
+            // Check from a pawn can be answered by capturing it en passant
+            ("8/8/8/2k5/3Pp3/8/8/4K3 b - d3 0 1", 9),
+        ];
+        for (fen, count) in positions {
+            let board = Board::from_fen(fen).unwrap();
+            let evasions = gen_moves(&board, MoveFilter::Evasions);
+            assert_eq!(evasions.len, count, "{}", fen);
+            assert_eq!(evasions.len, gen_legal_moves(&board, MoveFilter::All).len, "{}", fen);
+        }
+
+        // Outside of check every legal move is generated
+        let board = Board::new();
+        assert_eq!(gen_moves(&board, MoveFilter::Evasions).len, 20);
+    }
+
+    #[test]
+    fn test_legal_moves_match_pseudo_legal() {
+        let perft_suite = fs::read_to_string("resources/perft_suite.epd").unwrap();
+        for line in perft_suite.lines() {
+            let fen = line.split(';').next().unwrap().trim();
+            assert_legal_moves_match(&Board::from_fen(fen).unwrap(), 2);
+        }
+    }
+
+    #[test]
+    fn test_en_passant_edge_cases() {
+        // (fen, en passant capture, whether it is legal)
+        let positions = [
+            // Capturing en passant would expose the king along the rank
+            ("8/8/8/K1pP3r/8/8/8/7k w - c6 0 1", "d5c6", false),
+            // En passant captures the pawn giving check
+            ("8/8/8/2k5/3Pp3/8/8/4K3 b - d3 0 1", "e4d3", true),
+            // En passant along a diagonal pin is legal
+            ("8/8/1k6/8/3pP3/8/8/6BK b - e3 0 1", "d4e3", true),
+            // En passant off a diagonal pin is not
+            ("1k5b/8/8/3pP3/8/8/1K6/8 w - d6 0 1", "e5d6", false),
+            // En passant does not resolve a check from a piece
+            ("8/8/k7/2N5/2Pp4/8/8/4K3 b - c3 0 1", "d4c3", false),
+        ];
+        for (fen, ep, legal) in positions {
+            let board = Board::from_fen(fen).unwrap();
+            assert_legal_moves_match(&board, 1);
+            let found = gen_legal_moves(&board, MoveFilter::All).iter().any(|entry| entry.mv.to_uci() == ep);
+            assert_eq!(found, legal, "{}", fen);
+        }
+    }
+
+    fn assert_legal_moves_match(board: &Board, depth: u8) {
+        for filter in FILTERS {
+            let mut expected: Vec<Move> = gen_moves(board, filter).iter()
+                .map(|entry| entry.mv)
+                .filter(|mv| {
+                    let mut child = *board;
+                    child.make(mv);
+                    let legal = !is_check(&child, board.stm);
+                    assert_eq!(board.is_legal(mv), legal, "{} {}", board.to_fen(), mv.to_uci());
+                    legal
+                })
+                .collect();
+            let mut actual: Vec<Move> = gen_legal_moves(board, filter).iter().map(|entry| entry.mv).collect();
+            expected.sort_by_key(|mv| mv.0);
+            actual.sort_by_key(|mv| mv.0);
+            assert!(expected == actual, "{}", board.to_fen());
+        }
+        if depth > 1 {
+            for entry in gen_legal_moves(board, MoveFilter::All).iter() {
+                let mut child = *board;
+                child.make(&entry.mv);
+                assert_legal_moves_match(&child, depth - 1);
+            }
+        }
+    }
+
+}
@@ -4,7 +4,7 @@ use crate::thread::ThreadData;
 use crate::{movegen, see};
 use movegen::{gen_moves, MoveFilter};
 use Stage::{GenerateNoisies, GenerateQuiets, Quiets, TTMove};
-use crate::movepicker::Stage::{BadNoisies, Done, GoodNoisies};
+use crate::movepicker::Stage::{BadNoisies, Countermove, Done, Evasions, GenerateEvasions, GoodNoisies, Killer};
 use crate::see::see;
 use crate::types::bitboard::Bitboard;
 
@@ -13,18 +13,27 @@ pub enum Stage {
     TTMove,
     GenerateNoisies,
     GoodNoisies,
+    Killer,
+    Countermove,
     GenerateQuiets,
     Quiets,
     BadNoisies,
+    GenerateEvasions,
+    Evasions,
     Done
 }
 
+/// Evasions that capture are tried before those that don't.
+const EVASION_CAPTURE_BONUS: i32 = 1 << 28;
+
 pub struct MovePicker {
     moves: MoveList,
     filter: MoveFilter,
     idx: usize,
     pub stage: Stage,
     tt_move: Move,
+    killer: Move,
+    counter: Move,
     ply: usize,
     threats: Bitboard,
     pub skip_quiets: bool,
@@ -42,6 +51,8 @@ impl MovePicker {
             idx: 0,
             stage,
             tt_move,
+            killer: Move::NONE,
+            counter: Move::NONE,
             ply,
             threats,
             skip_quiets: false,
@@ -58,6 +69,8 @@ impl MovePicker {
             idx: 0,
             stage,
             tt_move,
+            killer: Move::NONE,
+            counter: Move::NONE,
             ply,
             threats,
             skip_quiets: true,
@@ -66,10 +79,49 @@ impl MovePicker {
         }
     }
 
+    /// A picker for positions in check, which yields only the moves that evade the check.
+    pub fn new_evasions(tt_move: Move, ply: usize, threats: Bitboard) -> Self {
+        let stage = if tt_move.exists() { TTMove } else { GenerateEvasions };
+        Self {
+            moves: MoveList::new(),
+            filter: MoveFilter::Evasions,
+            idx: 0,
+            stage,
+            tt_move,
+            killer: Move::NONE,
+            counter: Move::NONE,
+            ply,
+            threats,
+            skip_quiets: false,
+            see_threshold: None,
+            bad_noisies: MoveList::new(),
+        }
+    }
+
+    /// A picker for ProbCut, yielding only noisy moves that win at least the given SEE threshold
+    /// (plus the TT move, which the caller must verify).
+    pub fn new_probcut(tt_move: Move, see_threshold: i32, ply: usize, threats: Bitboard) -> Self {
+        let stage = if tt_move.exists() { TTMove } else { GenerateNoisies };
+        Self {
+            moves: MoveList::new(),
+            filter: MoveFilter::Noisies,
+            idx: 0,
+            stage,
+            tt_move,
+            killer: Move::NONE,
+            counter: Move::NONE,
+            ply,
+            threats,
+            skip_quiets: true,
+            see_threshold: Some(see_threshold),
+            bad_noisies: MoveList::new(),
+        }
+    }
+
     pub fn next(&mut self, board: &Board, td: &ThreadData) -> Option<Move> {
 
         if self.stage == TTMove {
-            self.stage = GenerateNoisies;
+            self.stage = if self.filter == MoveFilter::Evasions { GenerateEvasions } else { GenerateNoisies };
             if self.tt_move.exists() && board.is_pseudo_legal(&self.tt_move) {
                 return Some(self.tt_move);
             }
@@ -94,7 +146,28 @@ impl MovePicker {
                 return Some(best_move)
             } else {
                 self.idx = 0;
-                self.stage = GenerateQuiets;
+                self.stage = Killer;
+            }
+        }
+        if self.stage == Killer {
+            self.stage = Countermove;
+            if !self.skip_quiets {
+                if let Some(killer) = td.ss[self.ply].killer {
+                    if self.is_valid_quiet(board, killer) {
+                        self.killer = killer;
+                        return Some(killer);
+                    }
+                }
+            }
+        }
+        if self.stage == Countermove {
+            self.stage = GenerateQuiets;
+            if !self.skip_quiets {
+                let counter = td.countermove(board.stm, self.ply);
+                if counter != self.killer && self.is_valid_quiet(board, counter) {
+                    self.counter = counter;
+                    return Some(counter);
+                }
             }
         }
         if self.stage == GenerateQuiets {
@@ -128,6 +201,26 @@ impl MovePicker {
                 self.stage = Done;
             }
         }
+        if self.stage == GenerateEvasions {
+            self.idx = 0;
+            self.moves = gen_moves(board, MoveFilter::Evasions);
+            for entry in self.moves.iter() {
+                MovePicker::score(entry, board, td, self.ply, self.threats);
+                if board.captured(&entry.mv).is_some() {
+                    entry.score += EVASION_CAPTURE_BONUS;
+                }
+            }
+            self.stage = Evasions;
+        }
+        if self.stage == Evasions {
+            while let Some(best_move) = self.pick(false) {
+                if self.skip_quiets && board.captured(&best_move).is_none() {
+                    continue;
+                }
+                return Some(best_move);
+            }
+            self.stage = Done;
+        }
         None
 
     }
@@ -150,6 +243,15 @@ impl MovePicker {
 
     }
 
+    /// Killers and countermoves are taken from other positions, so they must be checked before
+    /// being played here. Captures are skipped, having already been tried with the noisy moves.
+    fn is_valid_quiet(&self, board: &Board, mv: Move) -> bool {
+        mv.exists()
+            && mv != self.tt_move
+            && board.captured(&mv).is_none()
+            && board.is_pseudo_legal(&mv)
+    }
+
     fn pick(&mut self, use_bad_noisies: bool) -> Option<Move> {
         let moves = if use_bad_noisies {
             &mut self.bad_noisies
@@ -178,7 +280,7 @@ impl MovePicker {
 
             if let Some(best_move) = moves.get(self.idx) {
                 let mv = best_move.mv;
-                if mv == self.tt_move {
+                if mv == self.tt_move || mv == self.killer || mv == self.counter {
                     self.idx += 1;
                     continue;
                 }
@@ -189,4 +291,117 @@ impl MovePicker {
         }
     }
 
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use crate::board::Board;
+    use crate::movegen::{gen_legal_moves, MoveFilter};
+    use crate::movepicker::MovePicker;
+    use crate::moves::Move;
+    use crate::thread::ThreadData;
+    use crate::types::bitboard::Bitboard;
+    use crate::types::piece::Piece;
+    use crate::types::side::Side;
+
+    fn find(board: &Board, uci: &str) -> Move {
+        let mut moves = gen_legal_moves(board, MoveFilter::All);
+        let mv = moves.iter().map(|entry| entry.mv).find(|mv| mv.to_uci() == uci);
+        mv.unwrap()
+    }
+
+    fn pick_all(picker: &mut MovePicker, board: &Board, td: &ThreadData) -> Vec<Move> {
+        let mut picked = Vec::new();
+        while let Some(mv) = picker.next(board, td) {
+            picked.push(mv);
+        }
+        picked
+    }
+
+    #[test]
+    fn test_killer_and_countermove_stages() {
+        let startpos = Board::from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1").unwrap();
+        let e2e4 = find(&startpos, "e2e4");
+        let mut board = startpos;
+        board.make(&e2e4);
+
+        let mut td: Box<ThreadData> = ThreadData::default().into();
+        td.ss[0].mv = Some(e2e4);
+        td.ss[0].pc = Some(Piece::Pawn);
+        td.ss[1].killer = Some(find(&board, "g8f6"));
+        td.countermoves.update(Side::Black, &e2e4, Piece::Pawn, &find(&board, "d7d5"));
+
+        // A white refutation of a pawn landing on e4 is kept apart from black's
+        td.countermoves.update(Side::White, &e2e4, Piece::Pawn, &find(&startpos, "g1f3"));
+
+        // With no captures available, the killer and countermove come first and are not repeated
+        let mut picker = MovePicker::new(Move::NONE, 1, Bitboard::empty());
+        let picked = pick_all(&mut picker, &board, &td);
+        assert_eq!(picked[0].to_uci(), "g8f6");
+        assert_eq!(picked[1].to_uci(), "d7d5");
+        assert_eq!(picked.len(), 20);
+
+        // The TT move is not repeated as a killer
+        let mut picker = MovePicker::new(find(&board, "g8f6"), 1, Bitboard::empty());
+        let picked = pick_all(&mut picker, &board, &td);
+        assert_eq!(picked[0].to_uci(), "g8f6");
+        assert_eq!(picked[1].to_uci(), "d7d5");
+        assert_eq!(picked.len(), 20);
+
+        // Killers and countermoves that are not pseudo-legal here are skipped
+        td.ss[1].killer = Some(e2e4);
+        td.countermoves.update(Side::Black, &e2e4, Piece::Pawn, &find(&startpos, "g1f3"));
+        let mut picker = MovePicker::new(Move::NONE, 1, Bitboard::empty());
+        let picked = pick_all(&mut picker, &board, &td);
+        assert!(!picked.contains(&e2e4));
+        assert_eq!(picked.len(), 20);
+
+        // Quiet stages are skipped entirely in quiescence search
+        td.ss[1].killer = Some(find(&board, "g8f6"));
+        let mut picker = MovePicker::new_qsearch(Move::NONE, MoveFilter::Captures, 1, Bitboard::empty());
+        assert!(pick_all(&mut picker, &board, &td).is_empty());
+    }
+
+    #[test]
+    fn test_countermove_stage_order() {
+        // After 1. e4 d5, white can capture on d5
+        let board = Board::from_fen("rnbqkbnr/ppp1pppp/8/3p4/4P3/8/PPPP1PPP/RNBQKBNR w KQkq d6 0 2").unwrap();
+        let d7d5 = Move::parse_uci("d7d5");
+        let e4d5 = find(&board, "e4d5");
+        let g1f3 = find(&board, "g1f3");
+        let b1c3 = find(&board, "b1c3");
+        let a2a3 = find(&board, "a2a3");
+
+        let mut td: Box<ThreadData> = ThreadData::default().into();
+        td.ss[1].mv = Some(d7d5);
+        td.ss[1].pc = Some(Piece::Pawn);
+        td.ss[2].killer = Some(g1f3);
+        td.countermoves.update(Side::White, &d7d5, Piece::Pawn, &b1c3);
+
+        // Quiets with a better history than the countermove still come after it
+        td.quiet_history.update(Side::White, &a2a3, Bitboard::empty(), 16000);
+        td.quiet_history.update(Side::White, &b1c3, Bitboard::empty(), -16000);
+
+        let mut picker = MovePicker::new(Move::NONE, 2, Bitboard::empty());
+        let picked = pick_all(&mut picker, &board, &td);
+        assert_eq!(&picked[..4], &[e4d5, g1f3, b1c3, a2a3]);
+        assert_eq!(picked.iter().filter(|&&mv| mv == b1c3).count(), 1);
+        assert_eq!(picked.len(), gen_legal_moves(&board, MoveFilter::All).len());
+    }
+
+    #[test]
+    fn test_evasion_picker() {
+        // The rook check can be answered by capturing it, blocking with the knight or moving the king
+        let board = Board::from_fen("4k3/8/8/8/8/3n4/8/4R1K1 b - - 0 1").unwrap();
+        let td: Box<ThreadData> = ThreadData::default().into();
+        let mut picker = MovePicker::new_evasions(Move::NONE, 0, Bitboard::empty());
+
+        let mut picked = Vec::new();
+        while let Some(mv) = picker.next(&board, &td) {
+            picked.push(mv);
+        }
+
+        assert_eq!(picked[0].to_uci(), "d3e1");
+        assert_eq!(picked.len(), gen_legal_moves(&board, MoveFilter::All).len);
+    }
+
+}
@@ -172,6 +172,12 @@ impl Move {
         self == Move::NONE
     }
 
+    /// Checks that the flag bits encode a known move flag. Moves read back from untrusted storage
+    /// (e.g. the transposition table) must pass this check before calling flag().
+    pub fn is_valid(self) -> bool {
+        (self.0 & FLAG_MASK) >> 12 <= MoveFlag::PromoN as u16
+    }
+
     pub const fn encoded(self) -> usize {
         (self.0 & 0b0000_1111_1111_1111) as usize
     }
@@ -264,7 +270,7 @@ impl MoveList {
 
         let sorted: ArrayVec<MoveListEntry, MAX_MOVES> = indices
             .into_iter()
-            .map(|i| self.list[i].clone())
+            .map(|i| self.list[i])
             .collect();
 
         self.list = sorted;
@@ -1,11 +1,14 @@
 use crate::types::side::Side::{Black, White};
 
 use crate::board::Board;
+use crate::fen;
 use crate::moves::Move;
 use crate::types::File;
 use crate::types::piece::{Piece, PIECES};
 use crate::types::side::Side;
 use crate::types::square::Square;
+use crate::types::Rank;
+use std::fmt;
 
 pub const FEATURES: usize = 768;
 pub const HIDDEN: usize = 1024;
@@ -58,15 +61,18 @@ pub struct Feature {
 }
 
 pub struct NNUE {
-    stack: [Accumulator; MAX_ACCUMULATORS],
+    stack: Box<[Accumulator; MAX_ACCUMULATORS]>,
     current: usize,
 }
 
 impl Default for NNUE {
     fn default() -> Self {
+        // The accumulator stack is over a megabyte, so build it on the heap rather than on the
+        // stack first, where it would overflow the stack of any thread that creates a ThreadData.
+        let stack = vec![Accumulator::default(); MAX_ACCUMULATORS].into_boxed_slice();
         NNUE {
             current: 0,
-            stack: [Accumulator::default(); MAX_ACCUMULATORS],
+            stack: stack.try_into().unwrap_or_else(|_| unreachable!()),
         }
     }
 }
@@ -104,6 +110,41 @@ impl NNUE {
         output
     }
 
+    /// Break down the evaluation of a position: the buckets used, the output, and how much each
+    /// piece is worth to the net. A piece's value is the change in eval when it is removed, found
+    /// by refreshing the accumulators without it, and is given from white's point of view.
+    pub fn trace(&mut self, board: &Board) -> EvalTrace {
+        self.activate(board);
+        let eval = self.evaluate(board);
+        let white_eval = if board.stm == White { eval } else { -eval };
+
+        let mut piece_values = [None; 64];
+        for sq in board.occ() & !board.pcs(Piece::King) {
+            let pc = board.piece_at(sq).unwrap();
+            let side = board.side_at(sq).unwrap();
+            let mut without = *board;
+            without.toggle_sq(sq, pc, side);
+            self.activate(&without);
+            let eval_without = self.evaluate(&without);
+            let white_eval_without = if board.stm == White { eval_without } else { -eval_without };
+            piece_values[sq] = Some(white_eval - white_eval_without);
+        }
+        self.activate(board);
+
+        EvalTrace {
+            board: *board,
+            king_buckets: [
+                king_bucket(board.king_sq(White), White),
+                king_bucket(board.king_sq(Black), Black),
+            ],
+            mirrored: [should_mirror(board.king_sq(White)), should_mirror(board.king_sq(Black))],
+            // The net has a single output head, so every position uses the same output bucket
+            output_bucket: 0,
+            eval,
+            piece_values,
+        }
+    }
+
     /// Activate the entire board from scratch. This initializes the accumulators based on the
     /// current board state, iterating over all pieces and their squares. Should be called only
     /// at the top of search, and then efficiently updated with each move.
@@ -207,6 +248,7 @@ impl NNUE {
     /// Update the accumulator for a capture move. The old piece is removed from the starting
     /// square, the new piece (potentially a promo piece) is added to the destination square, and
     /// the captured piece (potentially an en-passant pawn) is removed from the destination square.
+    #[allow(clippy::too_many_arguments)]
     fn handle_capture(&mut self,
                       mv: &Move,
                       pc: Piece,
@@ -458,24 +500,92 @@ impl Accumulator {
     }
 }
 
+/// The breakdown of an evaluation produced by `NNUE::trace`.
+pub struct EvalTrace {
+    pub board: Board,
+    pub king_buckets: [usize; 2],
+    pub mirrored: [bool; 2],
+    pub output_bucket: usize,
+    /// The net output from the side to move's point of view.
+    pub eval: i32,
+    /// The value of the piece on each square from white's point of view, if it can be removed.
+    pub piece_values: [Option<i32>; 64],
+}
+
+impl fmt::Display for EvalTrace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        const SEPARATOR: &str = " +-------+-------+-------+-------+-------+-------+-------+-------+";
+        writeln!(f, "King buckets: white {}{}, black {}{}",
+                 self.king_buckets[White], if self.mirrored[White] { " (mirrored)" } else { "" },
+                 self.king_buckets[Black], if self.mirrored[Black] { " (mirrored)" } else { "" })?;
+        writeln!(f, "Output bucket: {}", self.output_bucket)?;
+        writeln!(f)?;
+        writeln!(f, "Piece values (white's view, cp):")?;
+        writeln!(f, "{}", SEPARATOR)?;
+        for rank in (0..8).rev() {
+            let squares = (0..8).map(|file| Square::from(File::parse(file), Rank::parse(rank)));
+            for sq in squares.clone() {
+                let ch = match (self.board.piece_at(sq), self.board.side_at(sq)) {
+                    (Some(pc), Some(side)) => fen::piece_to_char(pc, side),
+                    _ => ' ',
+                };
+                write!(f, " |   {}  ", ch)?;
+            }
+            writeln!(f, " |")?;
+            for sq in squares {
+                match self.piece_values[sq] {
+                    Some(value) => write!(f, " | {:>+5}", value)?,
+                    None => write!(f, " |      ")?,
+                }
+            }
+            writeln!(f, " | {}", rank + 1)?;
+            writeln!(f, "{}", SEPARATOR)?;
+        }
+        writeln!(f, "     a       b       c       d       e       f       g       h")?;
+        writeln!(f)?;
+        write!(f, "Raw output: {} (side to move)", self.eval)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::board::Board;
     use crate::fen;
-    use crate::moves::Move;
     use crate::types::piece::Piece::Pawn;
     use crate::types::side::Side;
     use crate::types::square::Square;
+    use crate::types::piece::Piece;
     use super::{Feature, NNUE};
 
     #[test]
     fn test_startpos() {
-        let board = Board::from_fen(fen::STARTPOS);
+        let board = Board::from_fen(fen::STARTPOS).unwrap();
         let mut eval = NNUE::default();
         let score = eval.evaluate(&board);
         assert_eq!(score, 26);
     }
 
+    #[test]
+    fn test_trace() {
+        let board = Board::from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R b KQkq - 0 1").unwrap();
+        let mut nnue = NNUE::default();
+        let trace = nnue.trace(&board);
+
+        assert_eq!(trace.king_buckets, [3, 3]);
+        assert_eq!(trace.mirrored, [true, true]);
+        assert_eq!(trace.eval, nnue.evaluate(&board));
+        assert!(board.king(Side::White).chain(board.king(Side::Black)).all(|sq| trace.piece_values[sq].is_none()));
+        assert_eq!(trace.piece_values.iter().flatten().count(), board.occ().count() as usize - 2);
+
+        // Values are from white's point of view, even with black to move
+        let e5 = Square(36);
+        let mut without = board;
+        without.toggle_sq(e5, Piece::Knight, Side::White);
+        let mut other = NNUE::default();
+        other.activate(&without);
+        assert_eq!(trace.piece_values[e5], Some(other.evaluate(&without) - trace.eval));
+    }
+
     #[test]
     fn make_move_standard() {
 
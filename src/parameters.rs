@@ -0,0 +1,155 @@
+#[cfg(feature = "tune")]
+use std::sync::atomic::Ordering;
+
+/// A search parameter exposed for tuning.
+pub struct Tunable {
+    pub name: &'static str,
+    pub default: i32,
+    pub min: i32,
+    pub max: i32,
+    pub step: i32,
+}
+
+/// Declares the tunable search parameters. Each parameter becomes a function returning its
+/// value: a constant in normal builds, or a value adjustable over UCI with the `tune` feature.
+macro_rules! tunable {
+    ($($name:ident: $default:expr, $min:expr, $max:expr, $step:expr;)*) => {
+
+        #[cfg(feature = "tune")]
+        #[allow(non_upper_case_globals)]
+        mod values {
+            use std::sync::atomic::AtomicI32;
+            $(pub static $name: AtomicI32 = AtomicI32::new($default);)*
+        }
+
+        $(
+            #[cfg(not(feature = "tune"))]
+            #[inline(always)]
+            pub const fn $name() -> i32 {
+                $default
+            }
+
+            #[cfg(feature = "tune")]
+            #[inline(always)]
+            pub fn $name() -> i32 {
+                values::$name.load(Ordering::Relaxed)
+            }
+        )*
+
+        pub const TUNABLES: &[Tunable] = &[
+            $(Tunable { name: stringify!($name), default: $default, min: $min, max: $max, step: $step },)*
+        ];
+
+        /// Set the named parameter, returning an error if it does not exist or the value is out of range.
+        #[cfg(feature = "tune")]
+        pub fn set(name: &str, value: i32) -> Result<(), String> {
+            let tunable = TUNABLES.iter()
+                .find(|t| t.name == name)
+                .ok_or_else(|| format!("unknown option {}", name))?;
+            if value < tunable.min || value > tunable.max {
+                return Err(format!("{} must be a number between {} and {}", name, tunable.min, tunable.max));
+            }
+            match name {
+                $(stringify!($name) => values::$name.store(value, Ordering::Relaxed),)*
+                _ => unreachable!(),
+            }
+            Ok(())
+        }
+
+    };
+}
+
+tunable! {
+    asp_delta:              24,    8,   64,    4;
+    lmr_base:               92,    0,  200,   10;
+    lmr_divisor:           311,  150,  500,   20;
+    lmp_base:                1,    0,   10,    1;
+    lmp_scale:              39,   20,   80,    4;
+    lmp_improving_base:      3,    0,   10,    1;
+    lmp_improving_scale:    87,   40,  150,    6;
+    rfp_max_depth:           8,    4,   12,    1;
+    rfp_margin:             80,   40,  150,    8;
+    razor_base:            300,  100,  500,   25;
+    razor_scale:           250,  100,  400,   20;
+    nmp_min_depth:           3,    1,    6,    1;
+    nmp_base_reduction:      3,    1,    6,    1;
+    nmp_depth_divisor:       3,    1,    6,    1;
+    nmp_eval_divisor:      210,  100,  400,   20;
+    nmp_eval_max_reduction:  4,    1,    8,    1;
+    nmp_verification_depth: 14,    8,   20,    1;
+    pc_min_depth:            5,    3,    8,    1;
+    pc_margin:             200,  100,  350,   15;
+    pc_depth_reduction:      4,    2,    6,    1;
+    small_pc_margin:       400,  250,  600,   20;
+    se_double_margin:       20,    5,   60,    4;
+    se_triple_margin:      100,   40,  250,   10;
+    fp_base:               150,   50,  300,   12;
+    fp_scale:              100,   50,  200,    8;
+    hp_scale:             2048,  512, 4096,  128;
+    see_quiet_margin:       56,   20,  120,    6;
+    see_noisy_margin:       36,   10,   80,    4;
+    qs_fp_margin:          135,   50,  250,   10;
+    quiet_bonus_scale:     120,   50,  250,   10;
+    quiet_bonus_offset:     75,    0,  200,   10;
+    quiet_bonus_max:      1200,  500, 2500,  100;
+    quiet_malus_scale:     120,   50,  250,   10;
+    quiet_malus_offset:     75,    0,  200,   10;
+    quiet_malus_max:      1200,  500, 2500,  100;
+    capt_bonus_scale:      120,   50,  250,   10;
+    capt_bonus_offset:      75,    0,  200,   10;
+    capt_bonus_max:       1200,  500, 2500,  100;
+    capt_malus_scale:      120,   50,  250,   10;
+    capt_malus_offset:      75,    0,  200,   10;
+    capt_malus_max:       1200,  500, 2500,  100;
+    cont_bonus_scale:      120,   50,  250,   10;
+    cont_bonus_offset:      75,    0,  200,   10;
+    cont_bonus_max:       1200,  500, 2500,  100;
+    cont_malus_scale:      120,   50,  250,   10;
+    cont_malus_offset:      75,    0,  200,   10;
+    cont_malus_max:       1200,  500, 2500,  100;
+}
+
+/// The SPSA learning rate end value used for every parameter.
+const R_END: f32 = 0.002;
+
+impl Tunable {
+
+    /// The parameter in OpenBench's SPSA input format.
+    pub fn to_openbench(&self) -> String {
+        format!("{}, int, {}, {}, {}, {}, {}", self.name, self.default, self.min, self.max, self.step, R_END)
+    }
+
+    /// The parameter as an entry in a Weather Factory config.
+    pub fn to_weather_factory(&self) -> String {
+        format!("\"{}\": {{ \"value\": {}, \"min_value\": {}, \"max_value\": {}, \"step\": {} }}",
+                self.name, self.default, self.min, self.max, self.step)
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parameters::TUNABLES;
+
+    #[test]
+    fn test_tunables_in_range() {
+        for tunable in TUNABLES {
+            assert!(tunable.min <= tunable.default && tunable.default <= tunable.max, "{}", tunable.name);
+            assert!(tunable.step > 0, "{}", tunable.name);
+        }
+    }
+
+    #[test]
+    fn test_tunable_names_unique() {
+        for (i, tunable) in TUNABLES.iter().enumerate() {
+            assert!(TUNABLES[i + 1..].iter().all(|t| t.name != tunable.name), "{}", tunable.name);
+        }
+    }
+
+    #[test]
+    fn test_openbench_format() {
+        let tunable = TUNABLES.iter().find(|t| t.name == "asp_delta").unwrap();
+        assert_eq!(tunable.to_openbench(), "asp_delta, int, 24, 8, 64, 4, 0.002");
+    }
+
+}
@@ -1,78 +1,219 @@
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::thread;
+
 use crate::board::Board;
-use crate::movegen::{gen_moves, is_check, MoveFilter};
+use crate::movegen::{gen_legal_moves, MoveFilter};
+use crate::moves::Move;
 
 pub fn perft(board: &Board, depth: u8) -> u64 {
-    let moves = gen_moves(board, MoveFilter::All);
-    if depth == 1 {
-        let mut nodes = 0;
-        for i in 0..moves.len {
-            let mv = moves.list[i].mv;
-            let mut new_board = *board;
-            new_board.make(&mv);
-            if !is_check(&new_board, board.stm) {
-                nodes += 1;
-            }
+    let mut board = *board;
+    count_nodes(&mut board, depth, None)
+}
+
+/// Count the nodes below each root move, splitting the root moves between the given number of
+/// threads. Positions already counted to the same depth are looked up in the table, if given.
+pub fn divide(board: &Board, depth: u8, threads: usize, table: Option<&PerftTable>) -> Vec<(Move, u64)> {
+    if depth == 0 {
+        return Vec::new();
+    }
+    let moves: Vec<Move> = gen_legal_moves(board, MoveFilter::All).iter().map(|entry| entry.mv).collect();
+    let counts: Vec<AtomicU64> = moves.iter().map(|_| AtomicU64::new(0)).collect();
+    let next = AtomicUsize::new(0);
+
+    thread::scope(|scope| {
+        for _ in 0..threads.clamp(1, moves.len().max(1)) {
+            scope.spawn(|| {
+                let mut board = *board;
+                loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(mv) = moves.get(i) else {
+                        break;
+                    };
+                    let undo = board.make(mv);
+                    counts[i].store(count_nodes(&mut board, depth - 1, table), Ordering::Relaxed);
+                    board.unmake(mv, undo);
+                }
+            });
         }
+    });
+
+    moves.into_iter().zip(counts).map(|(mv, count)| (mv, count.into_inner())).collect()
+}
+
+fn count_nodes(board: &mut Board, depth: u8, table: Option<&PerftTable>) -> u64 {
+    if depth == 0 {
+        return 1;
+    }
+    let moves = gen_legal_moves(board, MoveFilter::All);
+    if depth == 1 {
+        // Bulk counting: the leaves are the legal moves, so there is no need to make them
+        return moves.len as u64;
+    }
+
+    if let Some(nodes) = table.and_then(|table| table.probe(board.hash, depth)) {
         return nodes;
     }
 
     let mut nodes = 0;
     for i in 0..moves.len {
         let mv = moves.list[i].mv;
-        let mut new_board = *board;
-        new_board.make(&mv);
-        if is_check(&new_board, board.stm) {
-            continue;
-        }
-        let new_nodes = perft(&new_board, depth - 1);
-        nodes += new_nodes;
+        let undo = board.make(&mv);
+        nodes += count_nodes(board, depth - 1, table);
+        board.unmake(&mv, undo);
     }
 
+    if let Some(table) = table {
+        table.store(board.hash, depth, nodes);
+    }
     nodes
 }
 
+/// A lock-free table of node counts, shared between perft threads. Each entry stores the key
+/// XOR-ed with the data, so that an entry torn by a concurrent write fails verification rather
+/// than returning a wrong count.
+pub struct PerftTable {
+    entries: Vec<PerftEntry>,
+    mask: usize,
+}
+
+#[derive(Default)]
+struct PerftEntry {
+    key: AtomicU64,
+    data: AtomicU64,
+}
+
+impl PerftTable {
+
+    pub fn new(size_mb: usize) -> Self {
+        let max_entries = (size_mb * 1024 * 1024 / size_of::<PerftEntry>()).max(1);
+        // Round down to a power of two so that the index can be masked
+        let len = 1 << max_entries.ilog2();
+        PerftTable {
+            entries: (0..len).map(|_| PerftEntry::default()).collect(),
+            mask: len - 1,
+        }
+    }
+
+    pub fn probe(&self, hash: u64, depth: u8) -> Option<u64> {
+        let entry = &self.entries[self.index(hash, depth)];
+        let data = entry.data.load(Ordering::Relaxed);
+        let key = entry.key.load(Ordering::Relaxed);
+        (key ^ data == hash && data as u8 == depth).then_some(data >> 8)
+    }
+
+    pub fn store(&self, hash: u64, depth: u8, nodes: u64) {
+        let entry = &self.entries[self.index(hash, depth)];
+        let data = nodes << 8 | depth as u64;
+        entry.key.store(hash ^ data, Ordering::Relaxed);
+        entry.data.store(data, Ordering::Relaxed);
+    }
+
+    fn index(&self, hash: u64, depth: u8) -> usize {
+        (hash ^ (depth as u64).wrapping_mul(0x9E3779B97F4A7C15)) as usize & self.mask
+    }
+
+}
+
+/// An expected perft result, read from an EPD line such as `<fen> ;D1 20 ;D2 400`.
+pub struct PerftCase {
+    pub fen: String,
+    pub depth: u8,
+    pub nodes: u64,
+}
+
+/// Parse the deepest case on each line of an EPD file, skipping depths above the maximum.
+pub fn parse_epd(epd: &str, max_depth: Option<u8>) -> Result<Vec<PerftCase>, String> {
+    let mut cases = Vec::new();
+    for (number, line) in epd.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()) {
+        let mut parts = line.split(';');
+        let fen = parts.next().unwrap_or_default().trim().to_string();
+        let mut deepest = None;
+        for part in parts {
+            let mut fields = part.split_whitespace();
+            let (Some(depth), Some(nodes)) = (fields.next(), fields.next()) else {
+                return Err(format!("line {}: invalid depth entry '{}'", number + 1, part.trim()));
+            };
+            let depth = depth.strip_prefix('D').and_then(|d| d.parse::<u8>().ok());
+            let (Some(depth), Ok(nodes)) = (depth, nodes.parse::<u64>()) else {
+                return Err(format!("line {}: invalid depth entry '{}'", number + 1, part.trim()));
+            };
+            if max_depth.is_none_or(|max| depth <= max) && deepest.is_none_or(|(d, _)| depth > d) {
+                deepest = Some((depth, nodes));
+            }
+        }
+        if let Some((depth, nodes)) = deepest {
+            cases.push(PerftCase { fen, depth, nodes });
+        }
+    }
+    Ok(cases)
+}
+
 #[cfg(test)]
 mod test {
     use crate::board::Board;
-    use crate::perft::perft;
+    use crate::fen::STARTPOS;
+    use crate::perft::{divide, parse_epd, perft, PerftTable};
     use std::fs;
 
-    pub const PERFT_SUITE: [(&str, &str, u8, u64); 3] = [
-        ("startpos", "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", 6, 119060324),
-        ("kiwipete", "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1", 5, 193690690),
-        ("en_passant_funhouse", "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1", 6, 11030083)
-    ];
-
     #[test]
     fn test_perft_suite() {
+        assert_perft_suite(Some(4));
+    }
 
-        println!("reading file...");
-        let perft_suite = fs::read_to_string("resources/perft_suite.epd").unwrap();
-        println!("parsed file!");
-
-        for perft_test in perft_suite.lines() {
-            let parts: Vec<&str> = perft_test.split(";").collect();
+    /// The full suite goes up to depth 6, which is too slow for a debug build. Run it with
+    /// `cargo test --release -- --ignored`.
+    #[test]
+    #[ignore]
+    fn test_perft_suite_full() {
+        assert_perft_suite(None);
+    }
 
-            println!("Parts: {:?}", parts);
-            let fen = parts[0];
+    #[test]
+    fn test_debug() {
+        let board = Board::from_fen(STARTPOS).unwrap();
+        assert_eq!(perft(&board, 5), 4865609);
+    }
 
-            let mut depth_nodes_str = parts.last().unwrap().split_whitespace();
-            let depth_str = depth_nodes_str.next().unwrap();
-            let nodes_str = depth_nodes_str.last().unwrap();
-            let depth: u8 = depth_str[1..].parse().unwrap();
-            let nodes: u64 = nodes_str.parse().unwrap();
+    #[test]
+    fn test_divide() {
+        let board = Board::from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1").unwrap();
+        let expected = divide(&board, 3, 1, None);
+        assert_eq!(expected.len(), 48);
+        assert_eq!(expected.iter().map(|(_, n)| n).sum::<u64>(), 97862);
 
-            println!("Running test on fen for depth {}: {}", depth, fen);
-            let board = Board::from_fen(fen);
-            assert_eq!(perft(&board, depth), nodes, "Failed test: {}", fen);
+        // Splitting the root moves between threads and hashing doesn't change any count
+        let table = PerftTable::new(1);
+        for _ in 0..2 {
+            let actual = divide(&board, 3, 4, Some(&table));
+            assert!(actual == expected);
         }
+
+        assert!(divide(&board, 0, 4, None).is_empty());
     }
 
     #[test]
-    fn test_debug() {
-        let fen = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
-        let board = Board::from_fen(fen);
-        assert_eq!(perft(&board, 5), 4865609);
+    fn test_parse_epd() {
+        let epd = "4k3/8/8/8/8/8/8/4K2R w K - 0 1 ;D1 15 ;D2 66 ;D3 1197\n\n8/8/8/8/8/8/8/K6k w - - 0 1 ;D2 9\n";
+        let cases = parse_epd(epd, None).unwrap();
+        assert_eq!(cases.len(), 2);
+        assert_eq!((cases[0].fen.as_str(), cases[0].depth, cases[0].nodes), ("4k3/8/8/8/8/8/8/4K2R w K - 0 1", 3, 1197));
+
+        // Lines with no case within the depth limit are skipped
+        let cases = parse_epd(epd, Some(1)).unwrap();
+        assert_eq!(cases.len(), 1);
+        assert_eq!((cases[0].depth, cases[0].nodes), (1, 15));
+
+        assert!(parse_epd("4k3/8/8/8/8/8/8/4K2R w K - 0 1 ;D1", None).is_err());
+        assert!(parse_epd("4k3/8/8/8/8/8/8/4K2R w K - 0 1 ;X1 15", None).is_err());
     }
 
-}
\ No newline at end of file
+    fn assert_perft_suite(max_depth: Option<u8>) {
+        let epd = fs::read_to_string("resources/perft_suite.epd").unwrap();
+        let table = PerftTable::new(16);
+        for case in parse_epd(&epd, max_depth).unwrap() {
+            let board = Board::from_fen(&case.fen).unwrap();
+            let nodes: u64 = divide(&board, case.depth, 4, Some(&table)).iter().map(|(_, n)| n).sum();
+            assert_eq!(nodes, case.nodes, "Failed test: {} at depth {}", case.fen, case.depth);
+        }
+    }
+}
@@ -2,13 +2,15 @@ use crate::board::Board;
 use crate::movegen::MoveFilter;
 use crate::movepicker::{MovePicker, Stage};
 use crate::moves::Move;
+use crate::parameters::*;
 use crate::see::see;
 use crate::thread::ThreadData;
 use crate::time::LimitType::{Hard, Soft};
+use crate::time::PanicReason;
 use crate::tt::TTFlag;
 use crate::tt::TTFlag::{Lower, Upper};
 use crate::types::piece::Piece;
-use crate::{movegen, see};
+use crate::{draw, movegen, see};
 use arrayvec::ArrayVec;
 use std::ops::{Index, IndexMut};
 use std::time::Instant;
@@ -16,44 +18,63 @@ use TTFlag::Exact;
 
 pub const MAX_PLY: usize = 256;
 
+const PANIC_MIN_DEPTH: i32 = 6;
+const PANIC_SCORE_DROP: i32 = 40;
+const PANIC_BEST_MOVE_DEPTH: i32 = 10;
+
 pub fn search(board: &Board, td: &mut ThreadData) -> (Move, i32) {
+    let mut board = *board;
+    let board = &mut board;
     td.start_time = Instant::now();
     td.best_move = Move::NONE;
+    td.root_stm = board.stm;
+    td.root_moves = RootMoves::new(board, &td.search_moves);
     td.nnue.activate(board);
 
     let mut alpha = Score::MIN;
     let mut beta = Score::MAX;
     let mut score = 0;
-    let mut delta = 24;
+    let mut delta = asp_delta();
+
+    let mut prev_best_move = Move::NONE;
+    let mut prev_score = 0;
+    let mut scored_moves = Vec::new();
 
     // Iterative Deepening
     while td.depth < MAX_DEPTH && !td.should_stop(Soft) {
 
+        // Search the root moves in order of how well they did in the previous iteration
+        td.root_moves.sort();
+        td.seldepth = 0;
+
         // Aspiration Windows
-        if td.depth >= 4 {
+        if td.depth >= 4 && !td.skill.enabled() {
             alpha = (score - delta).max(Score::MIN);
             beta = (score + delta).min(Score::MAX);
         }
 
+        let mut failed_low = false;
+        td.limits.reset_panic();
+
         loop {
             score = alpha_beta(board, td, td.depth, 0, alpha, beta, false);
 
-            if td.main {
-                if td.best_move.exists() {
-                    println!("info depth {} score cp {} pv {}", td.depth, score, td.best_move.to_uci());
-                } else {
-                    println!("info depth {} score cp {}", td.depth, score);
-                }
+            if td.should_stop(Hard) {
+                break;
             }
 
-            if td.should_stop(Hard) || Score::is_mate(score) {
-                break;
+            if td.main {
+                print_info(td, score);
             }
 
             match score {
                 s if s <= alpha => {
                     beta = (alpha + beta) / 2;
                     alpha = (score - delta).max(Score::MIN);
+                    if !failed_low {
+                        failed_low = true;
+                        extend_time(td, PanicReason::FailLow);
+                    }
                 }
                 s if s >= beta => {
                     beta = (score + delta).min(Score::MAX);
@@ -64,20 +85,98 @@ pub fn search(board: &Board, td: &mut ThreadData) -> (Move, i32) {
             delta += delta / 2;
         }
 
+        // The aborted iteration's score can't be trusted, and there is no point starting another
+        if td.should_stop(Hard) {
+            break;
+        }
+
+        scored_moves = td.root_moves.iter().map(|rm| (rm.mv, rm.score)).collect();
+
+        // Spend extra time if the score collapsed or the best move changed late in the search
+        if td.depth >= PANIC_MIN_DEPTH && !Score::is_mate(score) {
+            let score_drop = prev_score - score;
+            if score_drop >= PANIC_SCORE_DROP {
+                extend_time(td, PanicReason::ScoreDrop(score_drop));
+            } else if td.best_move != prev_best_move && td.depth >= PANIC_BEST_MOVE_DEPTH {
+                extend_time(td, PanicReason::BestMoveChange);
+            }
+        }
+
+        // Track how many consecutive iterations the best move and score have remained stable
+        td.best_move_stability = if td.best_move == prev_best_move {
+            td.best_move_stability + 1
+        } else {
+            0
+        };
+        td.eval_stability = if (score - prev_score).abs() <= 10 {
+            td.eval_stability + 1
+        } else {
+            0
+        };
+        prev_best_move = td.best_move;
+        prev_score = score;
+
+        // Stop as soon as a mate within the requested number of moves is proven
+        if td.limits.mate.is_some_and(|mate| score > 0 && Score::is_mate(score) && Score::mate_in(score) <= mate as i32) {
+            break;
+        }
+
         td.depth += 1;
     }
 
+    // Play a weaker move from the last fully searched iteration
+    if td.skill.enabled() && !scored_moves.is_empty() {
+        let seed = if td.deterministic { board.hash } else { td.start_time.elapsed().as_nanos() as u64 ^ board.hash };
+        td.best_move = td.skill.pick_move(&scored_moves, seed);
+        if let Some(&(_, skill_score)) = scored_moves.iter().find(|(mv, _)| *mv == td.best_move) {
+            score = skill_score;
+        }
+    }
+
     (td.best_move, score)
 }
 
+fn print_info(td: &ThreadData, score: i32) {
+    let pv = td.pv.line();
+    let pv = if pv.first() == Some(&td.best_move) {
+        pv.iter().map(|mv| mv.to_uci()).collect::<Vec<_>>().join(" ")
+    } else {
+        td.best_move.to_uci()
+    };
+    if td.best_move.exists() {
+        println!("info depth {} seldepth {} score {} pv {}", td.depth, td.seldepth, Score::to_uci(score), pv);
+    } else {
+        println!("info depth {} seldepth {} score {}", td.depth, td.seldepth, Score::to_uci(score));
+    }
+}
+
+fn extend_time(td: &mut ThreadData, reason: PanicReason) {
+    if td.deterministic || td.depth < PANIC_MIN_DEPTH {
+        return;
+    }
+    if let Some(scale) = td.limits.extend_soft_limit(reason) {
+        if td.main {
+            println!("info string depth {} {}, extending soft limit by {:.2}x",
+                     td.depth, reason.describe(), scale);
+        }
+    }
+}
+
 #[rustfmt::skip]
-fn alpha_beta(board: &Board, td: &mut ThreadData, mut depth: i32, ply: usize, mut alpha: i32, beta: i32, cut_node: bool) -> i32 {
+fn alpha_beta(board: &mut Board, td: &mut ThreadData, mut depth: i32, ply: usize, mut alpha: i32, mut beta: i32, cut_node: bool) -> i32 {
 
     // If search is aborted, exit immediately
     if td.should_stop(Hard) {
         return alpha;
     }
 
+    td.report_progress();
+
+    // A singular search shares its PV with the node that started it
+    if td.ss[ply].singular.is_none() {
+        td.pv.clear(ply);
+    }
+
     let threats = movegen::calc_threats(board, board.stm);
     let in_check = threats.contains(board.king_sq(board.stm));
 
@@ -91,15 +190,41 @@ fn alpha_beta(board: &Board, td: &mut ThreadData, mut depth: i32, ply: usize, mu
     }
 
     if ply > 0 && is_draw(td, board) {
-        return Score::DRAW;
+        return draw_score(td, board);
     }
 
     if ply >= MAX_PLY {
         return td.nnue.evaluate(board);
     }
 
+    td.seldepth = td.seldepth.max(ply);
+
     let root_node = ply == 0;
     let pv_node = beta - alpha > 1;
+    let mate_search = td.limits.mate.is_some();
+
+    // Weakened play chooses between root moves by score, so every root move needs an exact score
+    let score_root_moves = root_node && td.skill.enabled();
+
+    // Mate Distance Pruning
+    if !root_node {
+        alpha = alpha.max(-Score::MATE + ply as i32);
+        beta = beta.min(Score::MATE - ply as i32 - 1);
+        if alpha >= beta {
+            return alpha;
+        }
+    }
+
+    // Upcoming Repetition Detection
+    if ply > 0 {
+        let draw = draw_score(td, board);
+        if alpha < draw && td.has_upcoming_repetition(board, ply) {
+            alpha = draw;
+            if alpha >= beta {
+                return alpha;
+            }
+        }
+    }
 
     let singular = td.ss[ply].singular;
     let singular_search = singular.is_some();
@@ -143,44 +268,135 @@ fn alpha_beta(board: &Board, td: &mut ThreadData, mut depth: i32, ply: usize, mu
 
     let improving = is_improving(td, ply, static_eval);
 
-    if !root_node && !pv_node && !in_check && !singular_search{
+    if !root_node && !pv_node && !in_check && !singular_search && !mate_search {
 
         // Reverse Futility Pruning
-        if depth <= 8 && static_eval - 80 * (depth - improving as i32) >= beta {
+        if depth <= rfp_max_depth() && static_eval - rfp_margin() * (depth - improving as i32) >= beta {
             return beta + (static_eval - beta) / 3;
         }
 
         // Razoring
-        if !pv_node && static_eval < alpha - 300 - 250 * depth * depth {
+        if !pv_node && static_eval < alpha - razor_base() - razor_scale() * depth * depth {
             return qs(board, td, alpha, beta, ply);
         }
 
         // Null Move Pruning
-        if depth >= 3 && static_eval >= beta && board.has_non_pawns() {
-            let r = 3 + depth / 3 + ((static_eval - beta) / 210).min(4) + tt_move_noisy as i32;
-            let mut board = *board;
-            board.make_null_move();
+        if depth >= nmp_min_depth()
+            && static_eval >= beta
+            && has_null_move_material(board)
+            && (ply >= td.nmp_min_ply || board.stm != td.nmp_side) {
+            let r = nmp_base_reduction()
+                + depth / nmp_depth_divisor()
+                + ((static_eval - beta) / nmp_eval_divisor()).min(nmp_eval_max_reduction())
+                + tt_move_noisy as i32;
+            let undo = board.make_null_move();
             td.nodes += 1;
             td.keys.push(board.hash);
-            let score = -alpha_beta(&board, td, depth - r, ply + 1, -beta, -beta + 1, !cut_node);
+            let score = -alpha_beta(board, td, depth - r, ply + 1, -beta, -beta + 1, !cut_node);
             td.keys.pop();
+            board.unmake_null_move(undo);
 
             if score >= beta {
-                return score;
+                if td.nmp_min_ply > 0 || depth < nmp_verification_depth() {
+                    return score;
+                }
+
+                // Verification search: re-search with NMP disabled for this side to guard against zugzwang
+                td.nmp_min_ply = ply + 3 * (depth - r) as usize / 4;
+                td.nmp_side = board.stm;
+                let verified_score = alpha_beta(board, td, depth - r, ply, beta - 1, beta, false);
+                td.nmp_min_ply = 0;
+
+                if verified_score >= beta {
+                    return score;
+                }
+            }
+        }
+
+        // ProbCut
+        let pc_beta = beta + pc_margin();
+        if depth >= pc_min_depth()
+            && !Score::is_mate(beta)
+            && !(tt_hit && tt_depth >= depth - 3 && tt_score < pc_beta) {
+
+            let see_threshold = pc_beta - static_eval;
+            let mut move_picker = MovePicker::new_probcut(tt_move, see_threshold, ply, threats);
+
+            while let Some(mv) = move_picker.next(board, td) {
+
+                if move_picker.stage == Stage::BadNoisies {
+                    break;
+                }
+
+                if !board.is_noisy(&mv) || !see(board, &mv, see_threshold) || !board.is_legal(&mv) {
+                    continue;
+                }
+
+                let pc = board.piece_at(mv.from()).unwrap();
+                let captured = board.captured(&mv);
+
+                td.nnue.update(&mv, pc, captured, board);
+                let undo = board.make(&mv);
+
+                td.ss[ply].mv = Some(mv);
+                td.ss[ply].pc = Some(pc);
+                td.keys.push(board.hash);
+                td.nodes += 1;
+
+                // Verify with quiescence search before spending nodes on the reduced search
+                let mut score = -qs(board, td, -pc_beta, -pc_beta + 1, ply + 1);
+
+                if score >= pc_beta {
+                    let pc_depth = depth - pc_depth_reduction();
+                    score = -alpha_beta(board, td, pc_depth, ply + 1, -pc_beta, -pc_beta + 1, !cut_node);
+                }
+
+                td.ss[ply].mv = None;
+                td.ss[ply].pc = None;
+                td.keys.pop();
+                td.nnue.undo();
+                board.unmake(&mv, undo);
+
+                if td.should_stop(Hard) {
+                    return alpha;
+                }
+
+                if score >= pc_beta {
+                    td.tt.insert(board.hash, mv, score, (depth - 3) as u8, ply, Lower);
+                    return score;
+                }
             }
         }
 
     }
 
+    // Small ProbCut
+    let small_pc_beta = beta + small_pc_margin();
+    if !root_node
+        && !mate_search
+        && tt_hit
+        && tt_flag == Lower
+        && tt_depth >= depth - 4
+        && tt_score >= small_pc_beta
+        && !Score::is_mate(beta)
+        && !Score::is_mate(tt_score) {
+        return small_pc_beta;
+    }
+
     // Internal Iterative Reductions
     if !root_node
+        && !mate_search
         && depth >= 5
         && (pv_node || cut_node)
         && (!tt_hit || tt_move.is_null() || tt_depth < depth - 4) {
         depth -= 1;
     }
 
-    let mut move_picker = MovePicker::new(tt_move, ply, threats);
+    let mut move_picker = if in_check {
+        MovePicker::new_evasions(tt_move, ply, threats)
+    } else {
+        MovePicker::new(tt_move, ply, threats)
+    };
 
     let mut legal_moves = 0;
     let mut searched_moves = 0;
@@ -193,7 +409,9 @@ fn alpha_beta(board: &Board, td: &mut ThreadData, mut depth: i32, ply: usize, mu
     let mut quiets = ArrayVec::<Move, 32>::new();
     let mut captures = ArrayVec::<Move, 32>::new();
 
-    while let Some(mv) = move_picker.next(board, td) {
+    let mut root_idx = 0;
+
+    while let Some(mv) = next_move(board, td, &mut move_picker, root_node, &mut root_idx) {
 
         if !board.is_legal(&mv) {
             continue;
@@ -221,11 +439,12 @@ fn alpha_beta(board: &Board, td: &mut ThreadData, mut depth: i32, ply: usize, mu
         // Futility Pruning
         if !pv_node
             && !root_node
+            && !mate_search
             && !in_check
             && is_quiet
             && lmr_depth < 6
             && !is_mate_score
-            && static_eval + 100 * lmr_depth + 150 <= alpha {
+            && static_eval + fp_scale() * lmr_depth + fp_base() <= alpha {
             move_picker.skip_quiets = true;
             continue;
         }
@@ -233,6 +452,7 @@ fn alpha_beta(board: &Board, td: &mut ThreadData, mut depth: i32, ply: usize, mu
         // Late Move Pruning
         if !pv_node
             && !root_node
+            && !mate_search
             && !is_mate_score
             && is_quiet
             && depth <= 8
@@ -244,11 +464,12 @@ fn alpha_beta(board: &Board, td: &mut ThreadData, mut depth: i32, ply: usize, mu
         // History Pruning
         if !pv_node
             && !root_node
+            && !mate_search
             && !in_check
             && !is_mate_score
             && is_quiet
             && depth <= 4
-            && history_score < -2048 * depth * depth {
+            && history_score < -hp_scale() * depth * depth {
             move_picker.skip_quiets = true;
             continue
         }
@@ -256,6 +477,7 @@ fn alpha_beta(board: &Board, td: &mut ThreadData, mut depth: i32, ply: usize, mu
         // Bad Noisy Pruning
         let futility_margin = static_eval + 128 * lmr_depth;
         if !pv_node
+            && !mate_search
             && !in_check
             && lmr_depth < 6
             && move_picker.stage == Stage::BadNoisies
@@ -264,8 +486,9 @@ fn alpha_beta(board: &Board, td: &mut ThreadData, mut depth: i32, ply: usize, mu
         }
 
         // SEE Pruning
-        let see_threshold = if is_quiet { -56 * depth } else { -36 * depth * depth };
+        let see_threshold = if is_quiet { -see_quiet_margin() * depth } else { -see_noisy_margin() * depth * depth };
         if !pv_node
+            && !mate_search
             && depth <= 8
             && searched_moves >= 1
             && !Score::is_mate(best_score)
@@ -275,32 +498,44 @@ fn alpha_beta(board: &Board, td: &mut ThreadData, mut depth: i32, ply: usize, mu
 
         // Singular Extensions
         if !root_node
+            && !mate_search
             && !singular_search
             && tt_hit
             && mv == tt_move
             && depth >= 8
             && tt_flag != Upper
-            && tt_depth >= depth - 3 {
+            && tt_depth >= depth - 3
+            && (ply as i32) < 2 * td.depth {
 
             let s_beta = (tt_score - depth * 32 / 16).max(-Score::MATE + 1);
             let s_depth = (depth - 1) / 2;
 
             td.ss[ply].singular = Some(mv);
-            let score = alpha_beta(&board, td, s_depth, ply, s_beta - 1, s_beta, cut_node);
+            let score = alpha_beta(board, td, s_depth, ply, s_beta - 1, s_beta, cut_node);
             td.ss[ply].singular = None;
 
             if score < s_beta {
                 extension = 1;
-                extension += (!pv_node && score < s_beta - 20) as i32;
+                extension += (!pv_node && score < s_beta - se_double_margin()) as i32;
+                extension += (!pv_node && !tt_move_noisy && score < s_beta - se_triple_margin()) as i32;
+            } else if s_beta >= beta {
+                // Multicut: more than one move beats beta, so assume this node fails high
+                return s_beta;
             } else if tt_score >= beta {
+                extension = -2;
+            } else if cut_node {
                 extension = -1;
             }
 
         }
 
-        let mut board = *board;
-        td.nnue.update(&mv, pc, captured, &board);
-        board.make(&mv);
+        // Stop extending once the line is twice as long as the root depth, capping the total extensions
+        if (ply as i32) >= 2 * td.depth {
+            extension = extension.min(0);
+        }
+
+        td.nnue.update(&mv, pc, captured, board);
+        let undo = board.make(&mv);
 
         td.ss[ply].mv = Some(mv);
         td.ss[ply].pc = Some(pc);
@@ -309,13 +544,18 @@ fn alpha_beta(board: &Board, td: &mut ThreadData, mut depth: i32, ply: usize, mu
         searched_moves += 1;
         td.nodes += 1;
 
+        if root_node {
+            td.report_currmove(mv, root_idx);
+        }
+
         let initial_nodes = td.nodes;
         let new_depth = depth - 1 + extension;
 
         let mut score = Score::MIN;
 
-        // Principal Variation Search
-        if depth >= 3 && searched_moves > 3 + root_node as i32 + pv_node as i32 && is_quiet {
+        if score_root_moves {
+            score = -alpha_beta(board, td, new_depth, ply + 1, -beta, Score::MAX, false);
+        } else if depth >= 3 && searched_moves > 3 + root_node as i32 + pv_node as i32 && is_quiet && !mate_search {
             // Late Move Reductions
             let mut reduction = base_reduction;
             reduction += cut_node as i32;
@@ -327,18 +567,18 @@ fn alpha_beta(board: &Board, td: &mut ThreadData, mut depth: i32, ply: usize, mu
             let reduced_depth = (new_depth - reduction).clamp(1, new_depth);
 
             // Reduced-depth search
-            score = -alpha_beta(&board, td, reduced_depth, ply + 1, -alpha - 1, -alpha, true);
+            score = -alpha_beta(board, td, reduced_depth, ply + 1, -alpha - 1, -alpha, true);
 
             // Re-search if we reduced depth and score beat alpha
             if score > alpha && new_depth > reduced_depth {
-                score = -alpha_beta(&board, td, new_depth, ply + 1, -alpha - 1, -alpha, !cut_node);
+                score = -alpha_beta(board, td, new_depth, ply + 1, -alpha - 1, -alpha, !cut_node);
             }
         } else if !pv_node || searched_moves > 1 {
-            score = -alpha_beta(&board, td, new_depth, ply + 1, -alpha - 1, -alpha, !cut_node);
+            score = -alpha_beta(board, td, new_depth, ply + 1, -alpha - 1, -alpha, !cut_node);
         }
 
-        if pv_node && (searched_moves == 1 || score > alpha) {
-            score = -alpha_beta(&board, td, new_depth, ply + 1, -beta, -alpha, false);
+        if pv_node && !score_root_moves && (searched_moves == 1 || score > alpha) {
+            score = -alpha_beta(board, td, new_depth, ply + 1, -beta, -alpha, false);
         }
 
         if is_quiet && quiet_count < 32 {
@@ -353,15 +593,32 @@ fn alpha_beta(board: &Board, td: &mut ThreadData, mut depth: i32, ply: usize, mu
         td.ss[ply].pc = None;
         td.keys.pop();
         td.nnue.undo();
+        board.unmake(&mv, undo);
 
         if root_node {
-            td.node_table.add(&mv, td.nodes - initial_nodes);
+            td.root_moves.add_nodes(mv, td.nodes - initial_nodes);
         }
 
         if td.should_stop(Hard) {
             break;
         }
 
+        if root_node {
+            // Only the first move and moves that raise alpha get an exact score, unless every root
+            // move is searched with an open window. The rest sort behind them by node count.
+            if searched_moves == 1 || score > alpha || score_root_moves {
+                let seldepth = td.seldepth;
+                let rm = td.root_moves.get_mut(mv);
+                rm.score = score;
+                rm.seldepth = seldepth;
+                rm.pv.clear();
+                rm.pv.push(mv);
+                rm.pv.extend_from_slice(td.pv.line_at(1));
+            } else {
+                td.root_moves.get_mut(mv).score = Score::MIN;
+            }
+        }
+
         if score > best_score {
             best_score = score;
         }
@@ -373,6 +630,9 @@ fn alpha_beta(board: &Board, td: &mut ThreadData, mut depth: i32, ply: usize, mu
             if root_node {
                 td.best_move = mv;
             }
+            if pv_node {
+                td.pv.update(ply, mv);
+            }
 
             if score >= beta {
                 flag = TTFlag::Lower;
@@ -380,7 +640,7 @@ fn alpha_beta(board: &Board, td: &mut ThreadData, mut depth: i32, ply: usize, mu
             }
 
             // Alpha-raise reduction
-            if depth > 2 && depth < 12 && !is_mate_score {
+            if depth > 2 && depth < 12 && !is_mate_score && !score_root_moves {
                 depth -= 1;
             }
 
@@ -391,19 +651,24 @@ fn alpha_beta(board: &Board, td: &mut ThreadData, mut depth: i32, ply: usize, mu
     if best_move.exists() {
         let pc = board.piece_at(best_move.from()).unwrap();
 
-        let quiet_bonus = (120 * depth as i16 - 75).min(1200);
-        let quiet_malus = (120 * depth as i16 - 75).min(1200);
+        let quiet_bonus = (quiet_bonus_scale() * depth - quiet_bonus_offset()).min(quiet_bonus_max()) as i16;
+        let quiet_malus = (quiet_malus_scale() * depth - quiet_malus_offset()).min(quiet_malus_max()) as i16;
 
-        let capt_bonus = (120 * depth as i16 - 75).min(1200);
-        let capt_malus = (120 * depth as i16 - 75).min(1200);
+        let capt_bonus = (capt_bonus_scale() * depth - capt_bonus_offset()).min(capt_bonus_max()) as i16;
+        let capt_malus = (capt_malus_scale() * depth - capt_malus_offset()).min(capt_malus_max()) as i16;
 
-        let cont_bonus = (120 * depth as i16 - 75).min(1200);
-        let cont_malus = (120 * depth as i16 - 75).min(1200);
+        let cont_bonus = (cont_bonus_scale() * depth - cont_bonus_offset()).min(cont_bonus_max()) as i16;
+        let cont_malus = (cont_malus_scale() * depth - cont_malus_offset()).min(cont_malus_max()) as i16;
 
         if let Some(captured) = board.captured(&best_move) {
             td.capture_history.update(board.stm, pc, best_move.to(), captured, capt_bonus);
         } else {
             td.ss[ply].killer = Some(best_move);
+            if ply > 0 {
+                if let (Some(prev_mv), Some(prev_pc)) = (td.ss[ply - 1].mv, td.ss[ply - 1].pc) {
+                    td.countermoves.update(board.stm, &prev_mv, prev_pc, &best_move);
+                }
+            }
 
             td.quiet_history.update(board.stm, &best_move, threats, quiet_bonus);
             update_continuation_history(td, ply, &best_move, pc, cont_bonus);
@@ -432,7 +697,7 @@ fn alpha_beta(board: &Board, td: &mut ThreadData, mut depth: i32, ply: usize, mu
         } else if in_check {
             -Score::MATE + ply as i32
         } else {
-            Score::DRAW
+            draw_score(td, board)
         };
     }
 
@@ -453,21 +718,45 @@ fn alpha_beta(board: &Board, td: &mut ThreadData, mut depth: i32, ply: usize, mu
     best_score
 }
 
-fn qs(board: &Board, td: &mut ThreadData, mut alpha: i32, beta: i32, ply: usize) -> i32 {
+fn qs(board: &mut Board, td: &mut ThreadData, mut alpha: i32, mut beta: i32, ply: usize) -> i32 {
 
     // If search is aborted, exit immediately
     if td.should_stop(Hard) {
         return alpha;
     }
 
+    td.report_progress();
+
     if ply > 0 && is_draw(td, board) {
-        return Score::DRAW;
+        return draw_score(td, board);
     }
 
     if ply >= MAX_PLY {
         return td.nnue.evaluate(board);
     }
 
+    td.seldepth = td.seldepth.max(ply);
+
+    // Mate Distance Pruning
+    if ply > 0 {
+        alpha = alpha.max(-Score::MATE + ply as i32);
+        beta = beta.min(Score::MATE - ply as i32 - 1);
+        if alpha >= beta {
+            return alpha;
+        }
+    }
+
+    // Upcoming Repetition Detection
+    if ply > 0 {
+        let draw = draw_score(td, board);
+        if alpha < draw && td.has_upcoming_repetition(board, ply) {
+            alpha = draw;
+            if alpha >= beta {
+                return alpha;
+            }
+        }
+    }
+
     let threats = movegen::calc_threats(board, board.stm);
     let in_check = threats.contains(board.king_sq(board.stm));
 
@@ -497,16 +786,15 @@ fn qs(board: &Board, td: &mut ThreadData, mut alpha: i32, beta: i32, ply: usize)
         }
     }
 
-    let filter = if in_check {
-        MoveFilter::All
+    let mut move_picker = if in_check {
+        MovePicker::new_evasions(tt_move, ply, threats)
     } else {
-        MoveFilter::Captures
+        MovePicker::new_qsearch(tt_move, MoveFilter::Captures, ply, threats)
     };
-    let mut move_picker = MovePicker::new_qsearch(tt_move, filter, ply, threats);
 
     let mut move_count = 0;
 
-    let futility_margin = static_eval + 135;
+    let futility_margin = static_eval + qs_fp_margin();
     let mut best_score = static_eval;
 
     while let Some(mv) = move_picker.next(board, td) {
@@ -519,6 +807,12 @@ fn qs(board: &Board, td: &mut ThreadData, mut alpha: i32, beta: i32, ply: usize)
         let captured = board.captured(&mv);
         let is_mate_score = Score::is_mate(best_score);
 
+        // Evasion Pruning: once one evasion has shown we are not getting mated, skip quiet evasions
+        if in_check && move_count > 0 && !is_mate_score && captured.is_none() {
+            move_picker.skip_quiets = true;
+            continue;
+        }
+
         // Futility Pruning
         if !in_check && !is_mate_score && futility_margin <= alpha && !see::see(board, &mv, 1) {
             if best_score < futility_margin {
@@ -528,14 +822,13 @@ fn qs(board: &Board, td: &mut ThreadData, mut alpha: i32, beta: i32, ply: usize)
         }
 
         // SEE Pruning
-        if !in_check && !see::see(&board, &mv, 0) {
+        if !in_check && !see::see(board, &mv, 0) {
             continue;
         }
 
-        let mut board = *board;
-        td.nnue.update(&mv, pc, captured, &board);
+        td.nnue.update(&mv, pc, captured, board);
 
-        board.make(&mv);
+        let undo = board.make(&mv);
         td.ss[ply].mv = Some(mv);
         td.ss[ply].pc = Some(pc);
         td.keys.push(board.hash);
@@ -543,12 +836,13 @@ fn qs(board: &Board, td: &mut ThreadData, mut alpha: i32, beta: i32, ply: usize)
         move_count += 1;
         td.nodes += 1;
 
-        let score = -qs(&board, td, -beta, -alpha, ply + 1);
+        let score = -qs(board, td, -beta, -alpha, ply + 1);
 
         td.ss[ply].mv = None;
         td.ss[ply].pc = None;
         td.keys.pop();
         td.nnue.undo();
+        board.unmake(&mv, undo);
 
         if td.should_stop(Hard) {
             break;
@@ -575,7 +869,36 @@ fn qs(board: &Board, td: &mut ThreadData, mut alpha: i32, beta: i32, ply: usize)
 }
 
 fn is_draw(td: &ThreadData, board: &Board) -> bool {
-    board.is_fifty_move_rule() || board.is_insufficient_material() || td.is_repetition(board)
+    draw::is_draw(board, &td.keys, td.root_ply)
+}
+
+/// The score of a drawn position for the side to move. Contempt is applied relative to the side to
+/// move at the root, and the score is jittered by the node count so that the search doesn't treat
+/// every repetition line as interchangeable.
+fn draw_score(td: &ThreadData, board: &Board) -> i32 {
+    let contempt = if board.stm == td.root_stm { -td.contempt } else { td.contempt };
+    let jitter = 1 - (td.nodes & 2) as i32;
+    Score::DRAW + contempt + jitter
+}
+
+/// The next move to search. The root searches its precomputed root moves in order, while every
+/// other node generates moves in stages.
+fn next_move(board: &Board, td: &mut ThreadData, move_picker: &mut MovePicker, root_node: bool, root_idx: &mut usize) -> Option<Move> {
+    if root_node {
+        let mv = td.root_moves.iter().nth(*root_idx).map(|rm| rm.mv);
+        *root_idx += 1;
+        mv
+    } else {
+        move_picker.next(board, td)
+    }
+}
+
+/// Whether the side to move has a rook or queen, or at least two minor pieces. With less than that,
+/// zugzwang is common enough that passing can't be trusted to be worse than every real move.
+fn has_null_move_material(board: &Board) -> bool {
+    let majors = board.our(Piece::Rook) | board.our(Piece::Queen);
+    let minors = board.our(Piece::Knight) | board.our(Piece::Bishop);
+    !majors.is_empty() || minors.count() >= 2
 }
 
 fn is_improving(td: &ThreadData, ply: usize, static_eval: i32) -> bool {
@@ -600,8 +923,8 @@ fn is_improving(td: &ThreadData, ply: usize, static_eval: i32) -> bool {
 }
 
 fn late_move_threshold(depth: i32, improving: bool) -> i32 {
-    let base = if improving { 3 } else { 1 };
-    let scale = if improving { 87 } else { 39 };
+    let base = if improving { lmp_improving_base() } else { lmp_base() };
+    let scale = if improving { lmp_improving_scale() } else { lmp_scale() };
     (base + depth * scale) / 10
 }
 
@@ -616,7 +939,7 @@ fn update_continuation_history(td: &mut ThreadData, ply: usize, mv: &Move, pc: P
 }
 
 pub struct LmrTable {
-    table: [[i32; 64]; 256],
+    table: Box<[[i32; 64]; 256]>,
 }
 
 impl LmrTable {
@@ -627,10 +950,10 @@ impl LmrTable {
 
 impl Default for LmrTable {
     fn default() -> Self {
-        let base = 0.92;
-        let divisor = 3.11;
+        let base = lmr_base() as f32 / 100.0;
+        let divisor = lmr_divisor() as f32 / 100.0;
 
-        let mut table = [[0; 64]; 256];
+        let mut table = Box::new([[0; 64]; 256]);
 
         for depth in 1..256 {
             for move_count in 1..64 {
@@ -657,6 +980,113 @@ fn can_use_tt_move(board: &Board, tt_move: &Move) -> bool {
     tt_move.exists() && board.is_pseudo_legal(tt_move) && board.is_legal(tt_move)
 }
 
+pub struct PvTable {
+    table: Box<[[Move; MAX_PLY + 1]; MAX_PLY + 1]>,
+    len: [usize; MAX_PLY + 1],
+}
+
+impl Default for PvTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PvTable {
+    pub fn new() -> Self {
+        PvTable { table: Box::new([[Move::NONE; MAX_PLY + 1]; MAX_PLY + 1]), len: [0; MAX_PLY + 1] }
+    }
+
+    pub fn clear(&mut self, ply: usize) {
+        self.len[ply] = 0;
+    }
+
+    /// Set the PV at this ply to the given move followed by the PV of the child node.
+    pub fn update(&mut self, ply: usize, mv: Move) {
+        let child_len = self.len[ply + 1];
+        let (parent, child) = self.table.split_at_mut(ply + 1);
+        parent[ply][0] = mv;
+        parent[ply][1..=child_len].copy_from_slice(&child[0][..child_len]);
+        self.len[ply] = child_len + 1;
+    }
+
+    pub fn line(&self) -> &[Move] {
+        self.line_at(0)
+    }
+
+    pub fn line_at(&self, ply: usize) -> &[Move] {
+        &self.table[ply][..self.len[ply]]
+    }
+}
+
+/// A legal move at the root, with the results of searching it.
+#[derive(Clone)]
+pub struct RootMove {
+    pub mv: Move,
+    pub score: i32,
+    pub prev_score: i32,
+    pub pv: Vec<Move>,
+    pub seldepth: usize,
+    pub nodes: u64,
+}
+
+/// The moves searched at the root, built once per search from the legal moves in the position.
+#[derive(Default)]
+pub struct RootMoves {
+    moves: Vec<RootMove>,
+}
+
+impl RootMoves {
+
+    /// Build the root moves from the legal moves, restricted to the given moves if there are any.
+    pub fn new(board: &Board, search_moves: &[Move]) -> Self {
+        let moves = movegen::gen_legal_moves(board, MoveFilter::All).iter()
+            .map(|entry| entry.mv)
+            .filter(|mv| search_moves.is_empty() || search_moves.iter().any(|m| mv.matches(m)))
+            .map(|mv| RootMove { mv, score: Score::MIN, prev_score: Score::MIN, pv: Vec::new(), seldepth: 0, nodes: 0 })
+            .collect();
+        RootMoves { moves }
+    }
+
+    /// Carry the scores over from the last iteration, and order the moves by score. Moves that did
+    /// not get an exact score are ordered by the size of their subtrees.
+    pub fn sort(&mut self) {
+        for rm in self.moves.iter_mut() {
+            rm.prev_score = rm.score;
+        }
+        self.moves.sort_by(|a, b| b.prev_score.cmp(&a.prev_score).then(b.nodes.cmp(&a.nodes)));
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &RootMove> {
+        self.moves.iter()
+    }
+
+    pub fn get(&self, mv: Move) -> Option<&RootMove> {
+        self.moves.iter().find(|rm| rm.mv == mv)
+    }
+
+    fn get_mut(&mut self, mv: Move) -> &mut RootMove {
+        self.moves.iter_mut().find(|rm| rm.mv == mv).expect("searched a move that is not a root move")
+    }
+
+    pub fn add_nodes(&mut self, mv: Move, nodes: u64) {
+        self.get_mut(mv).nodes += nodes;
+    }
+
+    /// The number of nodes searched below the given move, or zero if it is not a root move.
+    pub fn nodes(&self, mv: Move) -> u64 {
+        self.get(mv).map_or(0, |rm| rm.nodes)
+    }
+
+    pub fn len(&self) -> usize {
+        self.moves.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.moves.is_empty()
+    }
+
+}
+
 pub struct SearchStack {
     data: [StackEntry; MAX_PLY + 8],
 }
@@ -717,4 +1147,134 @@ impl Score {
     pub fn is_mate(score: i32) -> bool {
         score.abs() >= Score::MATE - MAX_DEPTH
     }
-}
\ No newline at end of file
+
+    /// The number of moves until mate, negative if the side to move is being mated.
+    pub fn mate_in(score: i32) -> i32 {
+        if score > 0 {
+            (Score::MATE - score + 1) / 2
+        } else {
+            -(Score::MATE + score) / 2
+        }
+    }
+
+    pub fn to_uci(score: i32) -> String {
+        if Score::is_mate(score) {
+            format!("mate {}", Score::mate_in(score))
+        } else {
+            format!("cp {}", score)
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use crate::board::Board;
+    use crate::moves::Move;
+    use crate::search::{search, PvTable, RootMoves, Score};
+    use crate::thread::ThreadData;
+    use crate::tt::TranspositionTable;
+
+    // Zugzwang positions where the black bishop is locked in, so black has a piece but no safe way
+    // to pass, and white can only force mate by putting black to move in a losing position.
+    const ZUGZWANG_MATES: [&str; 3] = [
+        "8/6k1/8/7K/8/7R/1p6/bN6 w - - 0 1",
+        "1k6/5R2/8/3K4/8/8/1p6/bN6 w - - 0 1",
+        "8/5k2/8/6K1/8/1R6/1p6/bN6 w - - 0 1",
+    ];
+
+    #[test]
+    fn test_nmp_verification_finds_zugzwang_mates() {
+        for fen in ZUGZWANG_MATES {
+            let board = Board::from_fen(fen).unwrap();
+            let mut td: Box<ThreadData> = ThreadData::with_depth_limit(17).into();
+            td.tt = TranspositionTable::new(1);
+            td.keys.push(board.hash);
+
+            let (_, score) = search(&board, &mut td);
+
+            assert!(Score::is_mate(score) && score > 0, "{} scored {}", fen, score);
+        }
+    }
+
+    #[test]
+    fn test_mate_search() {
+        let board = Board::from_fen("kbK5/pp6/1P6/8/8/8/8/R7 w - - 0 1").unwrap();
+        let mut td: Box<ThreadData> = ThreadData::default().into();
+        td.tt = TranspositionTable::new(1);
+        td.limits.mate = Some(2);
+        td.reset();
+        td.keys.push(board.hash);
+
+        let (best_move, score) = search(&board, &mut td);
+
+        assert_eq!(best_move.to_uci(), "a1a6");
+        assert_eq!(Score::to_uci(score), "mate 2");
+        assert_eq!(td.pv.line().len(), 3);
+    }
+
+    #[test]
+    fn test_score_to_uci() {
+        assert_eq!(Score::to_uci(35), "cp 35");
+        assert_eq!(Score::to_uci(Score::MATE - 1), "mate 1");
+        assert_eq!(Score::to_uci(Score::MATE - 3), "mate 2");
+        assert_eq!(Score::to_uci(-Score::MATE + 2), "mate -1");
+        assert_eq!(Score::to_uci(-Score::MATE + 4), "mate -2");
+    }
+
+    #[test]
+    fn test_pv_table() {
+        let mut pv = PvTable::new();
+        pv.clear(2);
+        pv.update(1, Move::parse_uci("e7e5"));
+        pv.update(0, Move::parse_uci("e2e4"));
+        assert_eq!(pv.line(), &[Move::parse_uci("e2e4"), Move::parse_uci("e7e5")]);
+
+        pv.clear(1);
+        pv.update(0, Move::parse_uci("d2d4"));
+        assert_eq!(pv.line(), &[Move::parse_uci("d2d4")]);
+    }
+
+    #[test]
+    fn test_root_moves() {
+        let board = Board::new();
+        assert_eq!(RootMoves::new(&board, &[]).len(), 20);
+
+        let search_moves = [Move::parse_uci("e2e4"), Move::parse_uci("g1f3"), Move::parse_uci("e2e5")];
+        let root_moves = RootMoves::new(&board, &search_moves);
+        assert_eq!(root_moves.len(), 2);
+        assert!(root_moves.get(Move::parse_uci("d2d4")).is_none());
+    }
+
+    #[test]
+    fn test_root_moves_sort() {
+        let mut root_moves = RootMoves::new(&Board::new(), &[]);
+        let find = |uci: &str| root_moves.iter().find(|rm| rm.mv.to_uci() == uci).unwrap().mv;
+        let (e4, d4, nf3) = (find("e2e4"), find("d2d4"), find("g1f3"));
+        root_moves.get_mut(d4).score = 20;
+        root_moves.get_mut(e4).score = 30;
+        root_moves.add_nodes(nf3, 500);
+        root_moves.add_nodes(e4, 100);
+        root_moves.sort();
+
+        let order: Vec<Move> = root_moves.iter().take(3).map(|rm| rm.mv).collect();
+        assert_eq!(order, vec![e4, d4, nf3]);
+        assert_eq!(root_moves.get(e4).unwrap().prev_score, 30);
+    }
+
+    #[test]
+    fn test_root_moves_after_search() {
+        let board = Board::from_fen("kbK5/pp6/1P6/8/8/8/8/R7 w - - 0 1").unwrap();
+        let mut td: Box<ThreadData> = ThreadData::with_depth_limit(5).into();
+        td.tt = TranspositionTable::new(1);
+        td.keys.push(board.hash);
+
+        let (best_move, score) = search(&board, &mut td);
+
+        let rm = td.root_moves.get(best_move).unwrap();
+        assert_eq!(rm.score, score);
+        assert_eq!(rm.pv.first(), Some(&best_move));
+        assert!(rm.seldepth >= 3);
+        assert!(rm.nodes > 0);
+        assert!(td.root_moves.iter().map(|rm| rm.nodes).sum::<u64>() < td.nodes);
+    }
+
+}
@@ -17,10 +17,7 @@ pub fn see(board: &Board, mv: &Move, threshold: i32) -> bool {
     let from = mv.from();
     let to = mv.to();
 
-    let next_victim = mv.promo_piece().map_or_else(
-        || board.piece_at(from).unwrap(),
-        |promo| promo,
-    );
+    let next_victim = mv.promo_piece().unwrap_or_else(|| board.piece_at(from).unwrap());
 
     let mut balance = move_value(board, mv) - threshold;
 
@@ -156,8 +153,8 @@ mod tests {
             let threshold_str = parts[2].trim();
             let threshold: i32 = threshold_str.parse().unwrap();
 
-            let board = Board::from_fen(fen);
-            let moves = movegen::gen_moves(&board, MoveFilter::All);
+            let board = Board::from_fen(fen).unwrap();
+            let mut moves = movegen::gen_moves(&board, MoveFilter::All);
             let mv = moves.iter()
                 .map(|entry| entry.mv)
                 .find(|m| m.to_uci() == mv_uci)
@@ -187,8 +184,8 @@ mod tests {
         let threshold_str = parts[2].trim();
         let threshold: i32 = threshold_str.parse().unwrap();
 
-        let board = Board::from_fen(fen);
-        let moves = movegen::gen_moves(&board, MoveFilter::All);
+        let board = Board::from_fen(fen).unwrap();
+        let mut moves = movegen::gen_moves(&board, MoveFilter::All);
         let mv = moves.iter()
             .map(|entry| entry.mv)
             .find(|m| m.to_uci() == mv_uci)
@@ -0,0 +1,140 @@
+use crate::moves::Move;
+
+/// Weakens the engine for players who want a beatable opponent. A weakened search is capped in
+/// depth and nodes, scores every root move exactly, and then picks from the best few of them at
+/// random, favouring moves that lose less.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Skill {
+    level: f32,
+}
+
+/// The number of root moves the weakened engine chooses between.
+const CANDIDATES: usize = 4;
+
+impl Skill {
+
+    pub const MAX_LEVEL: i32 = 20;
+    pub const MIN_ELO: i32 = 1320;
+    pub const MAX_ELO: i32 = 3000;
+
+    pub fn new(level: i32) -> Skill {
+        Skill { level: level.clamp(0, Self::MAX_LEVEL) as f32 }
+    }
+
+    /// Map an Elo rating onto a fractional skill level. The mapping is linear and every limit
+    /// below is monotone in the level, so a higher Elo setting never plays weaker.
+    pub fn from_elo(elo: i32) -> Skill {
+        let elo = elo.clamp(Self::MIN_ELO, Self::MAX_ELO);
+        let fraction = (elo - Self::MIN_ELO) as f32 / (Self::MAX_ELO - Self::MIN_ELO) as f32;
+        // Even the top of the Elo range is weaker than full strength.
+        Skill { level: fraction * (Self::MAX_LEVEL - 1) as f32 }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.level < Self::MAX_LEVEL as f32
+    }
+
+    /// The exclusive depth limit, searching one ply at level 0 and one more per level.
+    pub fn depth_limit(&self) -> Option<u64> {
+        self.enabled().then(|| 2 + self.level as u64)
+    }
+
+    /// The node limit, doubling every two levels from a thousand nodes at level 0.
+    pub fn node_limit(&self) -> Option<u64> {
+        self.enabled().then(|| (1000.0 * 2f32.powf(self.level / 2.0)) as u64)
+    }
+
+    /// How willing the engine is to give up score, from 120 at level 0 down to 82 at level 19.
+    fn weakness(&self) -> u64 {
+        (120.0 - 2.0 * self.level) as u64
+    }
+
+    /// Pick a move from the scored root moves. Each of the best few moves gets a random bonus that
+    /// grows with both the weakness and the score it loses against the best move, so that weaker
+    /// levels are more likely to play worse moves, but large blunders stay rare.
+    pub fn pick_move(&self, scored_moves: &[(Move, i32)], seed: u64) -> Move {
+        let mut candidates = scored_moves.to_vec();
+        candidates.sort_by_key(|&(_, score)| -score);
+        candidates.truncate(CANDIDATES);
+
+        let Some(&(best_move, top_score)) = candidates.first() else {
+            return Move::NONE;
+        };
+        let worst_score = candidates.last().map_or(top_score, |&(_, score)| score);
+        let spread = (top_score - worst_score).min(100) as u64;
+        let weakness = self.weakness();
+
+        let mut rng = seed | 1;
+        let mut choice = (best_move, i32::MIN);
+        for &(mv, score) in candidates.iter() {
+            rng ^= rng << 13;
+            rng ^= rng >> 7;
+            rng ^= rng << 17;
+            let loss = (top_score - score) as u64;
+            let push = (weakness * loss + spread * (rng % weakness)) / 128;
+            let weighted = score + push as i32;
+            if weighted > choice.1 {
+                choice = (mv, weighted);
+            }
+        }
+        choice.0
+    }
+
+}
+
+impl Default for Skill {
+    fn default() -> Self {
+        Skill::new(Skill::MAX_LEVEL)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::moves::Move;
+    use crate::skill::Skill;
+
+    #[test]
+    fn test_full_strength() {
+        let skill = Skill::default();
+        assert!(!skill.enabled());
+        assert_eq!(skill.depth_limit(), None);
+        assert_eq!(skill.node_limit(), None);
+        assert!(Skill::from_elo(Skill::MAX_ELO).enabled());
+    }
+
+    #[test]
+    fn test_elo_is_monotone() {
+        let mut prev = Skill::from_elo(Skill::MIN_ELO);
+        for elo in (Skill::MIN_ELO..=Skill::MAX_ELO).step_by(10) {
+            let skill = Skill::from_elo(elo);
+            assert!(skill.depth_limit() >= prev.depth_limit(), "{}", elo);
+            assert!(skill.node_limit() >= prev.node_limit(), "{}", elo);
+            assert!(skill.weakness() <= prev.weakness(), "{}", elo);
+            prev = skill;
+        }
+        assert_eq!(Skill::from_elo(0), Skill::new(0));
+    }
+
+    #[test]
+    fn test_pick_move() {
+        let moves = [
+            (Move::parse_uci("e2e4"), 30),
+            (Move::parse_uci("d2d4"), 25),
+            (Move::parse_uci("g1f3"), 20),
+            (Move::parse_uci("g2g4"), -300),
+        ];
+
+        // A strong level never throws away a large amount of score
+        let skill = Skill::new(19);
+        assert!((0..1000).all(|seed| skill.pick_move(&moves, seed) != moves[3].0));
+
+        // A weak level picks between the close moves
+        let skill = Skill::new(0);
+        let picks: Vec<Move> = (0..1000).map(|seed| skill.pick_move(&moves, seed)).collect();
+        assert!(picks.contains(&moves[0].0));
+        assert!(picks.contains(&moves[1].0) || picks.contains(&moves[2].0));
+
+        assert_eq!(skill.pick_move(&[], 0), Move::NONE);
+    }
+
+}
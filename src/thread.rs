@@ -1,27 +1,42 @@
-use std::time::Instant;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use crate::board::Board;
-use crate::history::{CaptureHistory, ContinuationHistory, CorrectionHistory, QuietHistory};
+use crate::attacks;
+use crate::draw;
+use crate::cuckoo::CUCKOO;
+use crate::history::{CaptureHistory, ContinuationHistory, CorrectionHistory, CounterMoveTable, QuietHistory};
 use crate::moves::Move;
 use crate::network::NNUE;
-use crate::search::{LmrTable, SearchStack};
+use crate::search::{LmrTable, PvTable, RootMoves, SearchStack};
+use crate::skill::Skill;
 use crate::time::{LimitType, SearchLimits};
 use crate::tt::TranspositionTable;
 use crate::types::bitboard::Bitboard;
 use crate::types::piece::Piece;
 use crate::types::side::Side;
+use crate::zobrist::Zobrist;
+
+/// How many nodes to search between checks of whether a progress report is due.
+const PROGRESS_CHECK_NODES: u64 = 4096;
+
+/// How often to report search progress between iterations.
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
 
 pub struct ThreadData {
     pub id: usize,
     pub main: bool,
     pub tt: TranspositionTable,
     pub ss: SearchStack,
+    pub pv: PvTable,
     pub nnue: NNUE,
     pub keys: Vec<u64>,
     pub root_ply: usize,
     pub quiet_history: QuietHistory,
     pub capture_history: CaptureHistory,
     pub cont_history: ContinuationHistory,
+    pub countermoves: CounterMoveTable,
     pub pawn_corrhist: CorrectionHistory,
     pub nonpawn_corrhist: [CorrectionHistory; 2],
     pub countermove_corrhist: CorrectionHistory,
@@ -29,13 +44,26 @@ pub struct ThreadData {
     pub major_corrhist: CorrectionHistory,
     pub minor_corrhist: CorrectionHistory,
     pub lmr: LmrTable,
-    pub node_table: NodeTable,
+    pub root_moves: RootMoves,
+    pub search_moves: Vec<Move>,
     pub limits: SearchLimits,
     pub start_time: Instant,
     pub nodes: u64,
     pub depth: i32,
+    pub seldepth: usize,
     pub best_move: Move,
+    pub best_move_stability: usize,
+    pub eval_stability: usize,
     pub eval: i32,
+    pub nmp_min_ply: usize,
+    pub nmp_side: Side,
+    pub root_stm: Side,
+    pub contempt: i32,
+    pub deterministic: bool,
+    pub currmove_delay: u64,
+    pub last_progress: Instant,
+    pub skill: Skill,
+    pub cancelled: Arc<AtomicBool>,
 }
 
 impl Default for ThreadData {
@@ -45,12 +73,14 @@ impl Default for ThreadData {
             main: true,
             tt: TranspositionTable::new(64),
             ss: SearchStack::new(),
+            pv: PvTable::new(),
             nnue: NNUE::default(),
             keys: Vec::new(),
             root_ply: 0,
             quiet_history: QuietHistory::new(),
             capture_history: CaptureHistory::new(),
             cont_history: ContinuationHistory::new(),
+            countermoves: CounterMoveTable::new(),
             pawn_corrhist: CorrectionHistory::new(),
             nonpawn_corrhist: [CorrectionHistory::new(), CorrectionHistory::new()],
             countermove_corrhist: CorrectionHistory::new(),
@@ -58,31 +88,48 @@ impl Default for ThreadData {
             major_corrhist: CorrectionHistory::new(),
             minor_corrhist: CorrectionHistory::new(),
             lmr: LmrTable::default(),
-            node_table: NodeTable::new(),
-            limits: SearchLimits::new(None, None, None, None, None),
+            root_moves: RootMoves::default(),
+            search_moves: Vec::new(),
+            limits: SearchLimits::new(None, None, None, None, None, None, SearchLimits::DEFAULT_MOVE_OVERHEAD),
             start_time: Instant::now(),
             nodes: 0,
             depth: 0,
+            seldepth: 0,
             best_move: Move::NONE,
+            best_move_stability: 0,
+            eval_stability: 0,
             eval: 0,
+            nmp_min_ply: 0,
+            nmp_side: Side::White,
+            root_stm: Side::White,
+            contempt: 0,
+            deterministic: false,
+            currmove_delay: ThreadData::DEFAULT_CURRMOVE_DELAY,
+            last_progress: Instant::now(),
+            skill: Skill::default(),
+            cancelled: Arc::new(AtomicBool::new(false)),
         }
     }
 }
 
 impl ThreadData {
 
+    pub const DEFAULT_CURRMOVE_DELAY: u64 = 3000;
+
     pub fn with_depth_limit(depth: i32) -> Self {
         ThreadData {
             id: 0,
             main: true,
             tt: TranspositionTable::new(64),
             ss: SearchStack::new(),
+            pv: PvTable::new(),
             nnue: NNUE::default(),
             keys: Vec::new(),
             root_ply: 0,
             quiet_history: QuietHistory::new(),
             capture_history: CaptureHistory::new(),
             cont_history: ContinuationHistory::new(),
+            countermoves: CounterMoveTable::new(),
             pawn_corrhist: CorrectionHistory::new(),
             nonpawn_corrhist: [CorrectionHistory::new(), CorrectionHistory::new()],
             countermove_corrhist: CorrectionHistory::new(),
@@ -90,13 +137,26 @@ impl ThreadData {
             major_corrhist: CorrectionHistory::new(),
             minor_corrhist: CorrectionHistory::new(),
             lmr: LmrTable::default(),
-            node_table: NodeTable::new(),
-            limits: SearchLimits::new(None, None, None, None, Some(depth as u64)),
+            root_moves: RootMoves::default(),
+            search_moves: Vec::new(),
+            limits: SearchLimits::new(None, None, None, None, None, Some(depth as u64), SearchLimits::DEFAULT_MOVE_OVERHEAD),
             start_time: Instant::now(),
             nodes: 0,
             depth: 1,
+            seldepth: 0,
             best_move: Move::NONE,
+            best_move_stability: 0,
+            eval_stability: 0,
             eval: 0,
+            nmp_min_ply: 0,
+            nmp_side: Side::White,
+            root_stm: Side::White,
+            contempt: 0,
+            deterministic: false,
+            currmove_delay: ThreadData::DEFAULT_CURRMOVE_DELAY,
+            last_progress: Instant::now(),
+            skill: Skill::default(),
+            cancelled: Arc::new(AtomicBool::new(false)),
         }
     }
 
@@ -124,21 +184,33 @@ impl ThreadData {
 
     pub fn reset(&mut self) {
         self.ss = SearchStack::new();
+        self.pv = PvTable::new();
         self.start_time = Instant::now();
-        self.node_table.clear();
+        self.last_progress = self.start_time;
+        self.root_moves = RootMoves::default();
         self.nodes = 0;
         self.depth = 1;
+        self.seldepth = 0;
         self.best_move = Move::NONE;
+        self.best_move_stability = 0;
+        self.eval_stability = 0;
         self.eval = 0;
+        self.nmp_min_ply = 0;
     }
 
     pub fn clear(&mut self) {
-        self.tt.clear();
         self.keys.clear();
         self.root_ply = 0;
+        self.clear_tables();
+    }
+
+    /// Clear all state carried over from previous searches, leaving the game history intact.
+    pub fn clear_tables(&mut self) {
+        self.tt.clear();
         self.quiet_history.clear();
         self.capture_history.clear();
         self.cont_history.clear();
+        self.countermoves.clear();
         self.pawn_corrhist.clear();
         self.nonpawn_corrhist[Side::White].clear();
         self.nonpawn_corrhist[Side::Black].clear();
@@ -149,20 +221,53 @@ impl ThreadData {
     }
 
     pub fn is_repetition(&self, board: &Board) -> bool {
-        let curr_hash = board.hash;
-        let mut repetitions = 0;
-        let end = self.keys.len().saturating_sub(board.hm as usize + 1);
-        for ply in (end..self.keys.len().saturating_sub(2)).rev() {
-            let hash = self.keys[ply];
-            repetitions += u8::from(curr_hash == hash);
-
-            // Two-fold repetition of positions within the search tree
-            if repetitions == 1 && ply >= self.root_ply {
+        draw::is_repetition(board, &self.keys, self.root_ply)
+    }
+
+    /// Whether the side to move can repeat an earlier position with a single reversible move.
+    /// Positions before the root only count if they have already occurred twice.
+    pub fn has_upcoming_repetition(&self, board: &Board, ply: usize) -> bool {
+        let Some(curr) = self.keys.len().checked_sub(1) else {
+            return false;
+        };
+        let end = (board.hm as usize).min(curr);
+        if end < 3 {
+            return false;
+        }
+
+        let curr_hash = self.keys[curr];
+        let mut other = curr_hash ^ self.keys[curr - 1] ^ Zobrist::stm();
+
+        for i in (3..=end).step_by(2) {
+            other ^= self.keys[curr - i + 1] ^ self.keys[curr - i] ^ Zobrist::stm();
+
+            // The opponent's pieces must be back where they were
+            if other != 0 {
+                continue;
+            }
+
+            let Some(mv) = CUCKOO.lookup(curr_hash ^ self.keys[curr - i]) else {
+                continue;
+            };
+
+            let (from, to) = (mv.from(), mv.to());
+            if !(attacks::between(from, to) & board.occ()).is_empty() {
+                continue;
+            }
+
+            if ply > i {
                 return true;
             }
 
-            // Three-fold repetition including positions before search root
-            if repetitions == 2 {
+            // The move is stored in one direction only, so check which square the piece is on
+            let sq = if board.piece_at(from).is_some() { from } else { to };
+            if board.side_at(sq) != Some(board.stm) {
+                continue;
+            }
+
+            let earlier = curr - i;
+            let first = curr - end;
+            if (first..earlier.saturating_sub(3)).rev().step_by(2).any(|idx| self.keys[idx] == self.keys[earlier]) {
                 return true;
             }
         }
@@ -217,6 +322,16 @@ impl ThreadData {
         quiet_score + cont_score
     }
 
+    /// The stored refutation of the previous move by the side to move, if there was one.
+    pub fn countermove(&self, stm: Side, ply: usize) -> Move {
+        if ply >= 1 {
+            if let (Some(prev_mv), Some(prev_pc)) = (self.ss[ply - 1].mv, self.ss[ply - 1].pc) {
+                return self.countermoves.get(stm, prev_mv, prev_pc);
+            }
+        }
+        Move::NONE
+    }
+
     pub fn capture_history_score(&self, board: &Board, mv: &Move, pc: Piece, captured: Piece) -> i32 {
         self.capture_history.get(board.stm, pc, mv.to(), captured) as i32
     }
@@ -225,6 +340,32 @@ impl ThreadData {
         self.start_time.elapsed().as_millis()
     }
 
+    /// Periodically report the node count, speed and hash usage, so that GUIs have something to
+    /// show during long iterations. Disabled in deterministic mode, where output must not depend
+    /// on the clock.
+    #[inline]
+    pub fn report_progress(&mut self) {
+        if !self.main || self.deterministic || !self.nodes.is_multiple_of(PROGRESS_CHECK_NODES) {
+            return;
+        }
+        if self.last_progress.elapsed() < PROGRESS_INTERVAL {
+            return;
+        }
+        self.last_progress = Instant::now();
+        let time = self.time();
+        let nps = self.nodes as u128 * 1000 / time.max(1);
+        println!("info time {} nodes {} nps {} hashfull {}", time, self.nodes, nps, self.tt.hashfull());
+    }
+
+    /// Report the root move being searched, once the search has been running for long enough that
+    /// the GUI would otherwise show nothing.
+    pub fn report_currmove(&self, mv: Move, number: usize) {
+        if !self.main || self.deterministic || self.time() < self.currmove_delay as u128 {
+            return;
+        }
+        println!("info depth {} currmove {} currmovenumber {}", self.depth, mv.to_uci(), number);
+    }
+
     pub fn should_stop(&self, limit_type: LimitType) -> bool {
         if self.depth <= 1 {
             // Always clear the first depth, to ensure at least one legal move
@@ -237,10 +378,14 @@ impl ThreadData {
     }
 
     pub fn soft_limit_reached(&self) -> bool {
-        let best_move_nodes = self.node_table.get(&self.best_move);
-
-        if let Some(soft_time) = self.limits.scaled_soft_limit(self.depth, self.nodes, best_move_nodes) {
-            if self.start_time.elapsed() >= soft_time {
+        let best_move_nodes = self.root_moves.nodes(self.best_move);
+
+        if let Some(soft_time) = self.limits.scaled_soft_limit(self.depth,
+                                                               self.nodes,
+                                                               best_move_nodes,
+                                                               self.best_move_stability,
+                                                               self.eval_stability) {
+            if !self.deterministic && self.start_time.elapsed() >= soft_time {
                 return true;
             }
         }
@@ -251,8 +396,8 @@ impl ThreadData {
             }
         }
 
-        if let Some(depth_limit) = self.limits.depth {
-            if self.depth >= depth_limit as i32 {
+        if let Some(depth_limit) = self.limits.depth_limit() {
+            if self.depth >= depth_limit {
                 return true;
             }
         }
@@ -261,8 +406,12 @@ impl ThreadData {
     }
 
     pub fn hard_limit_reached(&self) -> bool {
+        if self.cancelled.load(Ordering::Relaxed) {
+            return true;
+        }
+
         if let Some(hard_time) = self.limits.hard_time {
-            if self.start_time.elapsed() >= hard_time {
+            if !self.deterministic && self.start_time.elapsed() >= hard_time {
                 return true;
             }
         }
@@ -273,8 +422,8 @@ impl ThreadData {
             }
         }
 
-        if let Some(depth_limit) = self.limits.depth {
-            if self.depth >= depth_limit as i32 {
+        if let Some(depth_limit) = self.limits.depth_limit() {
+            if self.depth >= depth_limit {
                 return true;
             }
         }
@@ -283,35 +432,14 @@ impl ThreadData {
     }
 }
 
-pub struct NodeTable {
-    table: [[u64; 64]; 64],
-}
-
-impl NodeTable {
-
-    pub fn new() -> Self {
-        NodeTable { table: [[0; 64]; 64] }
-    }
-
-    pub fn add(&mut self, mv: &Move, nodes: u64) {
-        self.table[mv.from()][mv.to()] += nodes;
-    }
-
-    pub fn get(&self, mv: &Move) -> u64 {
-        self.table[mv.from()][mv.to()]
-    }
-
-    pub fn clear(&mut self) {
-        *self = Self::new();
-    }
-}
-
-
 #[cfg(test)]
 mod tests {
     use crate::board::Board;
     use crate::moves::Move;
+    use crate::search::search;
     use crate::thread::ThreadData;
+    use crate::time::SearchLimits;
+    use crate::tt::TranspositionTable;
 
     #[test]
     fn test_twofold_rep_after_root() {
@@ -387,6 +515,90 @@ mod tests {
         assert!(td.is_repetition(&board));
     }
 
+    #[test]
+    fn test_upcoming_repetition() {
+        let mut td = ThreadData::default();
+        let mut board = Board::new();
+        td.keys.push(board.hash);
+
+        make_move(&mut td, &mut board, "g1f3");
+        make_move(&mut td, &mut board, "g8f6");
+        assert!(!td.has_upcoming_repetition(&board, 4));
+
+        // Black can repeat the start position with f6g8
+        make_move(&mut td, &mut board, "f3g1");
+        assert!(td.has_upcoming_repetition(&board, 4));
+
+        // At the root, the earlier position must already have repeated
+        assert!(!td.has_upcoming_repetition(&board, 0));
+    }
+
+    #[test]
+    fn test_upcoming_repetition_needs_opponent_to_return() {
+        let mut td = ThreadData::default();
+        let mut board = Board::new();
+        td.keys.push(board.hash);
+
+        make_move(&mut td, &mut board, "g1f3");
+        make_move(&mut td, &mut board, "g8f6");
+        make_move(&mut td, &mut board, "f3g5");
+        assert!(!td.has_upcoming_repetition(&board, 4));
+    }
+
+    #[test]
+    fn test_upcoming_repetition_before_root() {
+        let mut td = ThreadData::default();
+        let mut board = Board::new();
+        td.keys.push(board.hash);
+
+        for mv in ["g1f3", "g8f6", "f3g1", "f6g8", "g1f3", "g8f6", "f3g1"] {
+            make_move(&mut td, &mut board, mv);
+        }
+        td.root_ply = 7;
+
+        // The start position has already occurred twice, so repeating it again is a draw
+        assert!(td.has_upcoming_repetition(&board, 0));
+    }
+
+    #[test]
+    fn test_deterministic_search_is_reproducible() {
+        let board = Board::from_fen("r3k2r/2pb1ppp/2pp1q2/p7/1nP1B3/1P2P3/P2N1PPP/R2QK2R w KQkq a6 0 14").unwrap();
+        let mut td = deterministic_thread_data(5_000);
+
+        let first = (search(&board, &mut td), td.nodes, td.depth);
+
+        td.clear_tables();
+        td.reset();
+        let second = (search(&board, &mut td), td.nodes, td.depth);
+
+        let mut fresh_td = deterministic_thread_data(5_000);
+        let third = (search(&board, &mut fresh_td), fresh_td.nodes, fresh_td.depth);
+
+        assert_eq!(first, second);
+        assert_eq!(first, third);
+    }
+
+    #[test]
+    fn test_deterministic_search_ignores_clock() {
+        let board = Board::new();
+        let mut td = deterministic_thread_data(5_000);
+        td.limits = SearchLimits::new(Some((0, 0)), None, Some(0), None, Some(5_000), None, 0);
+
+        search(&board, &mut td);
+
+        assert!(td.depth > 2);
+        assert!(td.nodes >= 5_000);
+    }
+
+    fn deterministic_thread_data(nodes: u64) -> Box<ThreadData> {
+        let mut td: Box<ThreadData> = ThreadData::default().into();
+        td.tt = TranspositionTable::new(1);
+        td.deterministic = true;
+        td.limits = SearchLimits::new(None, None, None, None, Some(nodes), None, 0);
+        td.reset();
+        td
+    }
+
     fn make_move(td: &mut ThreadData, board: &mut Board, mv: &str) {
         let mv = Move::parse_uci(mv);
         board.make(&mv);
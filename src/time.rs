@@ -6,23 +6,60 @@ pub struct SearchLimits {
     pub soft_nodes: Option<u64>,
     pub hard_nodes: Option<u64>,
     pub depth:      Option<u64>,
+    pub mate:       Option<u64>,
+    panic_scale:    f32,
 }
 
 pub enum LimitType { Soft, Hard }
 
+/// Reasons for the search to spend more time than the soft limit originally allowed for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PanicReason {
+    /// The aspiration search failed low at the root, so the previous best move may be refuted.
+    FailLow,
+    /// The root score dropped by the given number of centipawns since the last iteration.
+    ScoreDrop(i32),
+    /// The best move changed in a late iteration.
+    BestMoveChange,
+}
+
+impl PanicReason {
+
+    fn scale(&self) -> f32 {
+        match self {
+            PanicReason::FailLow => 1.4,
+            PanicReason::ScoreDrop(drop) => 1.0 + (*drop).clamp(0, 200) as f32 / 200.0,
+            PanicReason::BestMoveChange => 1.25,
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            PanicReason::FailLow => "root fail low".to_string(),
+            PanicReason::ScoreDrop(drop) => format!("score dropped {}cp", drop),
+            PanicReason::BestMoveChange => "best move changed late".to_string(),
+        }
+    }
+
+}
+
 pub type FischerTime = (u64, u64);
 
 impl SearchLimits {
 
-    pub fn new(fischer:    Option<FischerTime>,
-               movetime:   Option<u64>,
-               soft_nodes: Option<u64>,
-               hard_nodes: Option<u64>,
-               depth:      Option<u64>) -> SearchLimits {
+    pub const DEFAULT_MOVE_OVERHEAD: u64 = 50;
+
+    pub fn new(fischer:       Option<FischerTime>,
+               movestogo:     Option<u64>,
+               movetime:      Option<u64>,
+               soft_nodes:    Option<u64>,
+               hard_nodes:    Option<u64>,
+               depth:         Option<u64>,
+               move_overhead: u64) -> SearchLimits {
 
         let (soft_time, hard_time) = match (fischer, movetime) {
             (Some(f), _) => {
-                let (soft, hard) = Self::calc_time_limits(f);
+                let (soft, hard) = Self::calc_time_limits(f, movestogo, move_overhead);
                 (Some(soft), Some(hard))
             }
             (None, Some(mt)) => {
@@ -38,17 +75,71 @@ impl SearchLimits {
             soft_nodes,
             hard_nodes,
             depth,
+            mate: None,
+            panic_scale: 1.0,
         }
     }
 
-    pub fn scaled_soft_limit(&self, depth: i32, nodes: u64, best_move_nodes: u64) -> Option<Duration> {
+    /// The depth at which to stop iterative deepening, if any. A mate in N moves is at most 2N - 1
+    /// plies deep, so a mate search that gets past that depth can stop: there is no such mate.
+    pub fn depth_limit(&self) -> Option<i32> {
+        min_limit(self.depth, self.mate.map(|mate| 2 * mate)).map(|depth| depth as i32)
+    }
+
+    /// Tighten the depth and node limits, keeping any stricter limits already in place.
+    pub fn cap(&mut self, depth: Option<u64>, nodes: Option<u64>) {
+        self.depth = min_limit(self.depth, depth);
+        self.hard_nodes = min_limit(self.hard_nodes, nodes);
+    }
+
+    pub fn scaled_soft_limit(&self,
+                             depth: i32,
+                             nodes: u64,
+                             best_move_nodes: u64,
+                             best_move_stability: usize,
+                             eval_stability: usize) -> Option<Duration> {
         self.soft_time.map(|soft_time| {
             let scaled = soft_time.as_secs_f32()
-                * self.node_tm_scale(depth, nodes, best_move_nodes);
+                * self.node_tm_scale(depth, nodes, best_move_nodes)
+                * self.best_move_stability_scale(best_move_stability)
+                * self.eval_stability_scale(eval_stability)
+                * self.panic_scale;
             Duration::from_secs_f32(scaled)
         })
     }
 
+    /// Extend the soft time limit for the current iteration in response to trouble at the root.
+    /// Only the strongest reason seen in the iteration counts, and the base soft limit is left
+    /// alone, so extensions don't compound from one iteration to the next. Returns the new scale if
+    /// the limit was extended.
+    pub fn extend_soft_limit(&mut self, reason: PanicReason) -> Option<f32> {
+        let (soft_time, hard_time) = (self.soft_time?, self.hard_time?);
+        if soft_time >= hard_time || reason.scale() <= self.panic_scale {
+            return None;
+        }
+        self.panic_scale = reason.scale();
+        Some(self.panic_scale)
+    }
+
+    /// Drop any extension from the previous iteration.
+    pub fn reset_panic(&mut self) {
+        self.panic_scale = 1.0;
+    }
+
+    /// Spend more time when the best move keeps changing between iterations, and less time once
+    /// it has stayed the same for several iterations in a row.
+    fn best_move_stability_scale(&self, stability: usize) -> f32 {
+        const SCALES: [f32; 5] = [2.43, 1.35, 1.09, 0.88, 0.68];
+        SCALES[stability.min(SCALES.len() - 1)]
+    }
+
+    /// Spend more time when the root score is fluctuating between iterations, and less time once
+    /// it has settled.
+    fn eval_stability_scale(&self, stability: usize) -> f32 {
+        const SCALES: [f32; 5] = [1.25, 1.15, 1.00, 0.94, 0.88];
+        SCALES[stability.min(SCALES.len() - 1)]
+    }
+
     fn node_tm_scale(&self, depth: i32, nodes: u64, best_move_nodes: u64) -> f32 {
         if depth < 4 || best_move_nodes == 0 {
             return 1.0;
@@ -57,14 +148,281 @@ impl SearchLimits {
         (1.5 - fraction) * 1.35
     }
 
-    fn calc_time_limits(fischer: FischerTime) -> (Duration, Duration) {
+    /// Allocate time for the current move. Under sudden death (plus increment) a fixed fraction of
+    /// the remaining clock is used. If the GUI tells us how many moves remain until the next time
+    /// control, the clock (plus the increments still to come) is instead divided evenly between
+    /// those moves, reserving the move overhead for each of them. The hard limit never exceeds a
+    /// fixed fraction of the clock left after the overhead, so later moves can still be played.
+    fn calc_time_limits(fischer: FischerTime, movestogo: Option<u64>, move_overhead: u64) -> (Duration, Duration) {
         let (time, inc) = (fischer.0 as f64, fischer.1 as f64);
-        let base = time * 0.05 + inc * 0.08;
+        let overhead = move_overhead as f64;
+        let base = match movestogo {
+            Some(mtg) => {
+                let mtg = mtg.clamp(1, 50) as f64;
+                let time_left = time + inc * (mtg - 1.0) - overhead * (mtg + 1.0);
+                time_left.max(0.0) / mtg
+            }
+            None => time * 0.05 + inc * 0.08,
+        };
+        let max_time = (time - overhead).max(0.0) * 0.75;
         let soft_time = base * 0.66;
         let hard_time = base * 2.0;
-        let soft = soft_time.min(time - 50.0);
-        let hard = hard_time.min(time - 50.0);
+        let soft = soft_time.min(max_time);
+        let hard = hard_time.min(max_time);
         (Duration::from_millis(soft as u64), Duration::from_millis(hard as u64))
     }
 
-}
\ No newline at end of file
+}
+
+fn min_limit(a: Option<u64>, b: Option<u64>) -> Option<u64> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        _ => a.or(b),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sudden_death() {
+        let limits = SearchLimits::new(Some((60000, 0)), None, None, None, None, None, 50);
+        assert_eq!(limits.soft_time, Some(Duration::from_millis(1980)));
+        assert_eq!(limits.hard_time, Some(Duration::from_millis(6000)));
+    }
+
+    #[test]
+    fn test_increment() {
+        let limits = SearchLimits::new(Some((60000, 1000)), None, None, None, None, None, 50);
+        assert_eq!(limits.soft_time, Some(Duration::from_millis(2032)));
+        assert_eq!(limits.hard_time, Some(Duration::from_millis(6160)));
+    }
+
+    #[test]
+    fn test_low_time() {
+        let limits = SearchLimits::new(Some((200, 1000)), None, None, None, None, None, 50);
+        assert_eq!(limits.hard_time, Some(Duration::from_millis(112)));
+        assert!(limits.soft_time.unwrap() <= limits.hard_time.unwrap());
+
+        let limits = SearchLimits::new(Some((10, 0)), None, None, None, None, None, 50);
+        assert_eq!(limits.soft_time, Some(Duration::ZERO));
+        assert_eq!(limits.hard_time, Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn test_move_overhead() {
+        let limits = SearchLimits::new(Some((1000, 1000)), None, None, None, None, None, 0);
+        assert_eq!(limits.hard_time, Some(Duration::from_millis(260)));
+
+        let limits = SearchLimits::new(Some((1000, 1000)), None, None, None, None, None, 900);
+        assert_eq!(limits.hard_time, Some(Duration::from_millis(75)));
+        assert_eq!(limits.soft_time, Some(Duration::from_millis(75)));
+    }
+
+    #[test]
+    fn test_movestogo() {
+        let limits = SearchLimits::new(Some((60000, 0)), Some(40), None, None, None, None, 50);
+        assert_eq!(limits.soft_time, Some(Duration::from_millis(956)));
+        assert_eq!(limits.hard_time, Some(Duration::from_millis(2897)));
+
+        // With one move left before the reset we can afford to spend most of the clock
+        let limits = SearchLimits::new(Some((10000, 0)), Some(1), None, None, None, None, 50);
+        assert_eq!(limits.soft_time, Some(Duration::from_millis(6534)));
+        assert_eq!(limits.hard_time, Some(Duration::from_millis(7462)));
+    }
+
+    #[test]
+    fn test_simulated_games() {
+        // (initial time, increment, moves per time control, move overhead)
+        let controls: [(u64, u64, Option<u64>, u64); 10] = [
+            (60000, 0, None, 50),
+            (10000, 100, None, 50),
+            (1000, 10, None, 10),
+            (180000, 2000, None, 100),
+            (60000, 0, Some(40), 50),
+            (60000, 1000, Some(40), 50),
+            (5000, 0, Some(10), 100),
+            (1000, 0, Some(1), 50),
+            (300000, 0, Some(60), 1000),
+            (60000, 0, Some(40), 1000),
+        ];
+
+        for (initial, inc, moves_per_tc, overhead) in controls {
+            // Without an increment or a reset, spending the hard limit on every move must
+            // eventually run the clock down to the latency, so only check the soft limit there.
+            let sudden_death = inc == 0 && moves_per_tc.is_none();
+            for spend_hard in [false, true] {
+                if spend_hard && sudden_death {
+                    continue;
+                }
+                let result = simulate_game(initial, inc, moves_per_tc, overhead, spend_hard);
+                assert!(result.is_ok(), "Flagged on move {:?} with time control {:?}",
+                        result, (initial, inc, moves_per_tc, overhead));
+            }
+        }
+    }
+
+    #[test]
+    fn test_simulated_cyclic_usage() {
+        // Under a repeating 40/60s control the engine should use most of each period rather than
+        // banking time it can never spend.
+        let (initial, moves_per_tc, overhead) = (60000, 40, 50);
+        let mut time = initial as i64;
+        for ply in 0..moves_per_tc {
+            let movestogo = moves_per_tc - ply;
+            let limits = SearchLimits::new(
+                Some((time as u64, 0)), Some(movestogo), None, None, None, None, overhead);
+            time -= limits.soft_time.unwrap().as_millis() as i64;
+        }
+        assert!(time >= 0);
+        assert!(time < initial as i64 / 10, "Left {}ms unused at the end of the period", time);
+    }
+
+    /// Plays out a game against the clock, where every move takes as long as the allocated soft or
+    /// hard limit plus the full move overhead worth of latency. Returns the move on which the
+    /// engine flagged, if any.
+    fn simulate_game(initial: u64, inc: u64, moves_per_tc: Option<u64>, overhead: u64, spend_hard: bool) -> Result<(), u64> {
+        let mut time = initial as i64;
+        for move_number in 0..100 {
+            let movestogo = moves_per_tc.map(|n| n - move_number % n);
+            let limits = SearchLimits::new(
+                Some((time as u64, inc)), movestogo, None, None, None, None, overhead);
+            let allocated = if spend_hard { limits.hard_time } else { limits.soft_time };
+            let spent = allocated.unwrap().as_millis() as i64 + overhead as i64;
+
+            time -= spent;
+            if time < 0 {
+                return Err(move_number);
+            }
+
+            time += inc as i64;
+            if movestogo == Some(1) {
+                time += initial as i64;
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_panic_extension() {
+        let mut limits = SearchLimits::new(Some((60000, 0)), None, None, None, None, None, 50);
+        let soft = limits.soft_time;
+        let unextended = limits.scaled_soft_limit(10, 1000, 500, 2, 2).unwrap();
+        assert_eq!(limits.extend_soft_limit(PanicReason::FailLow), Some(1.4));
+        assert!(limits.scaled_soft_limit(10, 1000, 500, 2, 2).unwrap() > unextended);
+        assert_eq!(limits.soft_time, soft);
+
+        // Within an iteration only the strongest reason counts
+        assert_eq!(limits.extend_soft_limit(PanicReason::BestMoveChange), None);
+        assert_eq!(limits.extend_soft_limit(PanicReason::ScoreDrop(200)), Some(2.0));
+        assert_eq!(limits.extend_soft_limit(PanicReason::ScoreDrop(200)), None);
+
+        limits.reset_panic();
+        assert_eq!(limits.scaled_soft_limit(10, 1000, 500, 2, 2).unwrap(), unextended);
+    }
+
+    #[test]
+    fn test_simulated_score_drops() {
+        // The root score drops sharply on every iteration while the best move stays the same. Each
+        // iteration takes 1.5x as long as the one before. The soft limit must stay the same from
+        // one iteration to the next rather than growing until the hard limit ends the search.
+        let mut limits = SearchLimits::new(Some((60000, 0)), None, None, None, None, None, 50);
+        let hard = limits.hard_time.unwrap();
+        let mut elapsed = Duration::ZERO;
+        let mut iteration = Duration::from_millis(10);
+        let mut first_limit = None;
+        for depth in 6.. {
+            limits.reset_panic();
+            elapsed += iteration;
+            iteration = iteration.mul_f32(1.5);
+            limits.extend_soft_limit(PanicReason::ScoreDrop(150));
+
+            let limit = limits.scaled_soft_limit(depth, 1000, 900, 4, 0).unwrap();
+            assert_eq!(limit, *first_limit.get_or_insert(limit), "soft limit changed at depth {}", depth);
+            if elapsed >= limit {
+                break;
+            }
+        }
+        assert!(elapsed < hard, "spent {:?} of a {:?} hard limit", elapsed, hard);
+    }
+
+    #[test]
+    fn test_panic_scales() {
+        assert!(PanicReason::ScoreDrop(200).scale() > PanicReason::ScoreDrop(50).scale());
+        assert_eq!(PanicReason::ScoreDrop(500).scale(), PanicReason::ScoreDrop(200).scale());
+        assert_eq!(PanicReason::ScoreDrop(-20).scale(), 1.0);
+    }
+
+    #[test]
+    fn test_panic_without_clock() {
+        let mut limits = SearchLimits::new(None, None, Some(1000), None, None, None, 50);
+        assert_eq!(limits.extend_soft_limit(PanicReason::FailLow), None);
+
+        let mut limits = SearchLimits::new(None, None, None, None, None, Some(10), 50);
+        assert_eq!(limits.extend_soft_limit(PanicReason::FailLow), None);
+    }
+
+    #[test]
+    fn test_cap() {
+        let mut limits = SearchLimits::new(None, None, None, None, Some(5000), None, 50);
+        limits.cap(Some(4), Some(10000));
+        assert_eq!(limits.depth, Some(4));
+        assert_eq!(limits.hard_nodes, Some(5000));
+
+        limits.cap(None, Some(1000));
+        assert_eq!(limits.depth, Some(4));
+        assert_eq!(limits.hard_nodes, Some(1000));
+    }
+
+    #[test]
+    fn test_movetime() {
+        let limits = SearchLimits::new(None, None, Some(500), None, None, None, 50);
+        assert_eq!(limits.soft_time, Some(Duration::from_millis(500)));
+        assert_eq!(limits.hard_time, Some(Duration::from_millis(500)));
+    }
+
+    #[test]
+    fn test_infinite() {
+        let limits = SearchLimits::new(None, None, None, None, None, Some(10), 50);
+        assert_eq!(limits.soft_time, None);
+        assert_eq!(limits.hard_time, None);
+        assert_eq!(limits.scaled_soft_limit(10, 1000, 500, 0, 0), None);
+    }
+
+    #[test]
+    fn test_best_move_stability() {
+        let limits = SearchLimits::new(Some((60000, 0)), None, None, None, None, None, 50);
+        let unstable = limits.scaled_soft_limit(10, 1000, 500, 0, 2).unwrap();
+        let settling = limits.scaled_soft_limit(10, 1000, 500, 2, 2).unwrap();
+        let stable = limits.scaled_soft_limit(10, 1000, 500, 4, 2).unwrap();
+        let very_stable = limits.scaled_soft_limit(10, 1000, 500, 20, 2).unwrap();
+        assert!(unstable > settling);
+        assert!(settling > stable);
+        assert_eq!(stable, very_stable);
+    }
+
+    #[test]
+    fn test_eval_stability() {
+        let limits = SearchLimits::new(Some((60000, 0)), None, None, None, None, None, 50);
+        let unstable = limits.scaled_soft_limit(10, 1000, 500, 2, 0).unwrap();
+        let neutral = limits.scaled_soft_limit(10, 1000, 500, 2, 2).unwrap();
+        let stable = limits.scaled_soft_limit(10, 1000, 500, 2, 4).unwrap();
+        assert!(unstable > neutral);
+        assert!(neutral > stable);
+    }
+
+    #[test]
+    fn test_node_fraction() {
+        let limits = SearchLimits::new(Some((60000, 0)), None, None, None, None, None, 50);
+        let focused = limits.scaled_soft_limit(10, 1000, 900, 2, 2).unwrap();
+        let spread = limits.scaled_soft_limit(10, 1000, 100, 2, 2).unwrap();
+        assert!(focused < spread);
+
+        // Node scaling only kicks in from depth 4
+        let shallow = limits.scaled_soft_limit(3, 1000, 900, 2, 2).unwrap();
+        let unscaled = limits.scaled_soft_limit(3, 1000, 100, 2, 2).unwrap();
+        assert_eq!(shallow, unscaled);
+    }
+
+}
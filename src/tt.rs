@@ -10,7 +10,7 @@ pub struct TranspositionTable {
 #[derive(Clone)]
 #[derive(Default)]
 pub struct TTEntry {
-    key: u16,           // 2 bytes
+    key: u16,           // 2 bytes (XOR'd with the entry checksum)
     best_move: u16,     // 2 bytes
     score: i16,         // 2 bytes
     depth: u8,          // 1 byte
@@ -26,12 +26,12 @@ pub enum TTFlag {
 
 impl TTFlag {
 
-    pub fn from_u8(val: u8) -> TTFlag {
+    pub fn from_u8(val: u8) -> Option<TTFlag> {
         match val {
-            0 => TTFlag::Exact,
-            1 => TTFlag::Lower,
-            2 => TTFlag::Upper,
-            _ => panic!("Invalid hash flag value"),
+            0 => Some(TTFlag::Exact),
+            1 => Some(TTFlag::Lower),
+            2 => Some(TTFlag::Upper),
+            _ => None,
         }
     }
 
@@ -60,11 +60,30 @@ impl TTEntry {
     }
 
     pub fn flag(&self) -> TTFlag {
-        TTFlag::from_u8(self.flag)
+        // Entries returned by probe have already been validated, so the fallback is never used.
+        TTFlag::from_u8(self.flag).unwrap_or(TTFlag::Upper)
     }
 
     pub fn validate_key(&self, key: u64) -> bool {
-        self.key == (key & 0xFFFF) as u16
+        self.key ^ self.checksum() == (key & 0xFFFF) as u16
+    }
+
+    /// Checks that the entry belongs to the given hash and that its contents decode cleanly. The
+    /// stored key is XOR'd with a checksum of the entry data, so a torn or corrupted write will
+    /// almost always fail the key check and be treated as a miss.
+    pub fn is_valid(&self, key: u64) -> bool {
+        self.validate_key(key)
+            && TTFlag::from_u8(self.flag).is_some()
+            && Move(self.best_move).is_valid()
+            && self.score as i32 >= Score::MIN
+    }
+
+    fn is_empty(&self) -> bool {
+        self.key == 0 && self.best_move == 0 && self.score == 0 && self.depth == 0 && self.flag == 0
+    }
+
+    fn checksum(&self) -> u16 {
+        self.best_move ^ self.score as u16 ^ ((self.depth as u16) << 8 | self.flag as u16)
     }
 
 }
@@ -101,7 +120,7 @@ impl TranspositionTable {
     pub fn probe(&self, hash: u64) -> Option<&TTEntry> {
         let idx = self.idx(hash);
         let entry = &self.table[idx];
-        if entry.validate_key(hash) {
+        if entry.is_valid(hash) {
             Some(entry)
         } else {
             None
@@ -113,17 +132,27 @@ impl TranspositionTable {
         let entry = &mut self.table[idx];
 
         let key_part = (hash & 0xFFFF) as u16;
-        let key_match = key_part == entry.key;
+        let key_match = entry.is_valid(hash);
 
         if !best_move.exists() && key_match {
             best_move = entry.best_move();
         }
 
-        entry.key = key_part;
         entry.best_move = best_move.0;
         entry.score = to_tt(score, ply);
         entry.depth = depth;
         entry.flag = flag.to_u8();
+        entry.key = key_part ^ entry.checksum();
+    }
+
+    /// The permille of entries in use, estimated from the start of the table.
+    pub fn hashfull(&self) -> usize {
+        let sample = self.table.len().min(1000);
+        if sample == 0 {
+            return 0;
+        }
+        let used = self.table[..sample].iter().filter(|entry| !entry.is_empty()).count();
+        used * 1000 / sample
     }
 
     fn idx(&self, hash: u64) -> usize {
@@ -145,13 +174,17 @@ fn to_search(score: i32, ply: usize) -> i16 {
     if !Score::is_mate(score) {
         return score as i16
     }
-    if score > 0 { (score + ply as i32) as i16 } else { (score - ply as i32) as i16 }
+    let score = if score > 0 { score + ply as i32 } else { score - ply as i32 };
+    score.clamp(Score::MIN, Score::MAX) as i16
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::board::Board;
     use crate::moves::MoveFlag;
+    use crate::search::search;
+    use crate::thread::ThreadData;
     use crate::types::square::Square;
 
     #[test]
@@ -174,4 +207,120 @@ mod tests {
         assert_eq!(entry.flag(), flag);
     }
 
+    #[test]
+    fn test_hashfull() {
+        let mut tt = TranspositionTable::new(1);
+        assert_eq!(tt.hashfull(), 0);
+
+        // Entries are indexed by the top 16 bits of the hash
+        for i in 0..500u64 {
+            tt.insert(i << 48, Move::NONE, 10, 4, 0, TTFlag::Lower);
+        }
+        assert_eq!(tt.hashfull(), 500);
+
+        tt.clear();
+        assert_eq!(tt.hashfull(), 0);
+    }
+
+    #[test]
+    fn test_flag_from_u8() {
+        assert_eq!(TTFlag::from_u8(0), Some(TTFlag::Exact));
+        assert_eq!(TTFlag::from_u8(1), Some(TTFlag::Lower));
+        assert_eq!(TTFlag::from_u8(2), Some(TTFlag::Upper));
+        assert_eq!(TTFlag::from_u8(3), None);
+        assert_eq!(TTFlag::from_u8(255), None);
+    }
+
+    #[test]
+    fn test_corrupted_entry_is_miss() {
+        let mut tt = TranspositionTable::new(1);
+        let hash = 0x1234567890ABCDEF;
+        let best_move = Move::new(Square(12), Square(28), MoveFlag::DoublePush);
+        tt.insert(hash, best_move, 35, 7, 0, TTFlag::Lower);
+        assert!(tt.probe(hash).is_some());
+
+        let idx = tt.idx(hash);
+        tt.table[idx].score ^= 0x40;
+        assert!(tt.probe(hash).is_none());
+
+        tt.insert(hash, best_move, 35, 7, 0, TTFlag::Lower);
+        tt.table[idx].flag = 7;
+        assert!(tt.probe(hash).is_none());
+    }
+
+    #[test]
+    fn test_search_random_table() {
+        let mut rng = Rng(0x9E3779B97F4A7C15);
+        for fen in FUZZ_FENS {
+            let board = Board::from_fen(fen).unwrap();
+            let mut td = fuzz_thread_data();
+            for entry in td.tt.table.iter_mut() {
+                *entry = random_entry(&mut rng);
+            }
+            let (best_move, _) = search(&board, &mut td);
+            assert!(best_move.exists(), "No move found for {}", fen);
+        }
+    }
+
+    #[test]
+    fn test_search_random_payloads() {
+        let mut rng = Rng(0xD1B54A32D192ED03);
+        for fen in FUZZ_FENS {
+            let board = Board::from_fen(fen).unwrap();
+            let mut td = fuzz_thread_data();
+            search(&board, &mut td);
+
+            // Scramble the payload of every entry while keeping the checksum intact, so that
+            // the search is fed real keys with garbage moves, scores, depths and flags.
+            for entry in td.tt.table.iter_mut() {
+                let original_key = entry.key ^ entry.checksum();
+                let random = random_entry(&mut rng);
+                entry.best_move = random.best_move;
+                entry.score = random.score;
+                entry.depth = random.depth;
+                entry.flag = random.flag % 4;
+                entry.key = original_key ^ entry.checksum();
+            }
+
+            td.reset();
+            let (best_move, _) = search(&board, &mut td);
+            assert!(best_move.exists(), "No move found for {}", fen);
+        }
+    }
+
+    const FUZZ_FENS: [&str; 4] = [
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+        "r3k2r/2pb1ppp/2pp1q2/p7/1nP1B3/1P2P3/P2N1PPP/R2QK2R w KQkq a6 0 14",
+        "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+        "rnbqkb1r/pP3ppp/5n2/4p3/8/8/PPPP1PPP/RNBQKBNR w KQkq - 0 5",
+    ];
+
+    fn fuzz_thread_data() -> Box<ThreadData> {
+        let mut td: Box<ThreadData> = ThreadData::with_depth_limit(5).into();
+        td.tt = TranspositionTable::new(1);
+        td
+    }
+
+    fn random_entry(rng: &mut Rng) -> TTEntry {
+        let bytes = rng.next().to_le_bytes();
+        TTEntry {
+            key: u16::from_le_bytes([bytes[0], bytes[1]]),
+            best_move: u16::from_le_bytes([bytes[2], bytes[3]]),
+            score: i16::from_le_bytes([bytes[4], bytes[5]]),
+            depth: bytes[6],
+            flag: bytes[7],
+        }
+    }
+
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+    }
+
 }
@@ -1,15 +1,24 @@
 use std::io;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc;
+use std::thread;
 
 use crate::types::side::Side::{Black, White};
 
 use crate::bench::bench;
 use crate::board::Board;
 use crate::fen;
-use crate::movegen::{gen_moves, MoveFilter};
+use crate::movegen::{gen_legal_moves, MoveFilter};
 use crate::moves::Move;
 use crate::network::NNUE;
-use crate::perft::perft;
-use crate::search::search;
+use crate::parameters::TUNABLES;
+use crate::perft::{divide, parse_epd, PerftTable};
+use crate::search::{search, RootMoves, Score};
+use crate::skill::Skill;
+#[cfg(feature = "tune")]
+use crate::search::LmrTable;
+#[cfg(feature = "tune")]
+use crate::parameters;
 use crate::thread::ThreadData;
 use crate::time::SearchLimits;
 
@@ -17,6 +26,10 @@ pub struct UCI {
     pub board: Board,
     pub td: Box<ThreadData>,
     pub nnue: Box<NNUE>,
+    pub move_overhead: u64,
+    pub skill_level: i32,
+    pub limit_strength: bool,
+    pub elo: i32,
 }
 
 impl Default for UCI {
@@ -31,6 +44,10 @@ impl UCI {
             board: Board::new(),
             td: ThreadData::default().into(),
             nnue: NNUE::default().into(),
+            move_overhead: SearchLimits::DEFAULT_MOVE_OVERHEAD,
+            skill_level: Skill::MAX_LEVEL,
+            limit_strength: false,
+            elo: Skill::MAX_ELO,
         }
     }
 
@@ -40,32 +57,55 @@ impl UCI {
             self.handle_bench();
             return;
         }
+        if args.len() > 1 && args[1] == "perft-suite" {
+            self.handle_perft_suite(&args[1..]);
+            return;
+        }
 
         println!("🐅🐅🐅 Hobbes by Dan Kelsey 🐅🐅🐅");
         println!("(type 'help' for a list of commands)");
 
-        loop {
-            let mut command = String::new();
-            io::stdin()
-                .read_line(&mut command)
-                .expect("info error failed to parse command");
+        // Read stdin on its own thread, so that 'stop' and 'quit' can cancel a search in progress.
+        // The flag stays set until the main loop reaches the 'stop' itself, so a stop that arrives
+        // before its search has started still cancels it.
+        let cancelled = self.td.cancelled.clone();
+        let (sender, receiver) = mpsc::channel::<String>();
+        thread::spawn(move || {
+            for command in io::stdin().lines().map_while(Result::ok) {
+                if matches!(command.split_ascii_whitespace().next(), Some("stop") | Some("quit")) {
+                    cancelled.store(true, Ordering::Relaxed);
+                }
+                if sender.send(command).is_err() {
+                    break;
+                }
+            }
+        });
 
+        for command in receiver {
             let tokens: Vec<String> = command
                 .split_whitespace()
                 .map(|v| v.trim().to_string())
                 .collect();
 
-            match command.split_ascii_whitespace().next().unwrap() {
+            let Some(name) = tokens.first() else {
+                continue;
+            };
+
+            match name.as_str() {
                 "uci" => self.handle_uci(),
                 "isready" => self.handle_isready(),
+                "setoption" => self.handle_setoption(tokens),
                 "ucinewgame" => self.handle_ucinewgame(),
                 "bench" => self.handle_bench(),
                 "position" => self.handle_position(tokens),
                 "go" => self.handle_go(tokens),
                 "stop" => self.handle_stop(),
                 "fen" => self.handle_fen(),
-                "eval" => self.handle_eval(),
+                "d" => self.handle_display(),
+                "eval" => self.handle_eval(tokens),
                 "perft" => self.handle_perft(tokens),
+                "perft-suite" => self.handle_perft_suite(&tokens),
+                "tune-config" => self.handle_tune_config(tokens),
                 "help" => self.handle_help(),
                 "quit" => self.handle_quit(),
                 _ => println!("info error: unknown command"),
@@ -76,9 +116,98 @@ impl UCI {
     fn handle_uci(&self) {
         println!("id name Hobbes");
         println!("id author Dan Kelsey");
+        println!("option name MoveOverhead type spin default {} min 0 max 5000", SearchLimits::DEFAULT_MOVE_OVERHEAD);
+        println!("option name Deterministic type check default false");
+        println!("option name CurrMoveDelay type spin default {} min 0 max 60000", ThreadData::DEFAULT_CURRMOVE_DELAY);
+        println!("option name Contempt type spin default 0 min -100 max 100");
+        println!("option name Skill Level type spin default {} min 0 max {}", Skill::MAX_LEVEL, Skill::MAX_LEVEL);
+        println!("option name UCI_LimitStrength type check default false");
+        println!("option name UCI_Elo type spin default {} min {} max {}", Skill::MAX_ELO, Skill::MIN_ELO, Skill::MAX_ELO);
+        #[cfg(feature = "tune")]
+        for tunable in TUNABLES {
+            println!("option name {} type spin default {} min {} max {}", tunable.name, tunable.default, tunable.min, tunable.max);
+        }
         println!("uciok");
     }
 
+    fn handle_setoption(&mut self, tokens: Vec<String>) {
+        let name_idx = tokens.iter().position(|t| t == "name");
+        let value_idx = tokens.iter().position(|t| t == "value");
+        let (Some(name_idx), Some(value_idx)) = (name_idx, value_idx) else {
+            println!("info error: setoption must be of the form 'setoption name <name> value <value>'");
+            return;
+        };
+        let name = tokens[name_idx + 1..value_idx].join(" ");
+        let value = tokens[value_idx + 1..].join(" ");
+
+        match name.as_str() {
+            "MoveOverhead" => match value.parse::<u64>() {
+                Ok(overhead) if overhead <= 5000 => self.move_overhead = overhead,
+                _ => println!("info error: MoveOverhead must be a number between 0 and 5000"),
+            },
+            "Contempt" => match value.parse::<i32>() {
+                Ok(contempt) if (-100..=100).contains(&contempt) => self.td.contempt = contempt,
+                _ => println!("info error: Contempt must be a number between -100 and 100"),
+            },
+            "Skill Level" => match value.parse::<i32>() {
+                Ok(level) if (0..=Skill::MAX_LEVEL).contains(&level) => self.skill_level = level,
+                _ => println!("info error: Skill Level must be a number between 0 and {}", Skill::MAX_LEVEL),
+            },
+            "UCI_LimitStrength" => match value.parse::<bool>() {
+                Ok(limit_strength) => self.limit_strength = limit_strength,
+                _ => println!("info error: UCI_LimitStrength must be true or false"),
+            },
+            "UCI_Elo" => match value.parse::<i32>() {
+                Ok(elo) if (Skill::MIN_ELO..=Skill::MAX_ELO).contains(&elo) => self.elo = elo,
+                _ => println!("info error: UCI_Elo must be a number between {} and {}", Skill::MIN_ELO, Skill::MAX_ELO),
+            },
+            "CurrMoveDelay" => match value.parse::<u64>() {
+                Ok(delay) if delay <= 60000 => self.td.currmove_delay = delay,
+                _ => println!("info error: CurrMoveDelay must be a number between 0 and 60000"),
+            },
+            "Deterministic" => match value.parse::<bool>() {
+                Ok(deterministic) => self.td.deterministic = deterministic,
+                _ => println!("info error: Deterministic must be true or false"),
+            },
+            #[cfg(feature = "tune")]
+            _ => self.set_tunable(&name, &value),
+            #[cfg(not(feature = "tune"))]
+            _ => println!("info error: unknown option {}", name),
+        }
+    }
+
+    #[cfg(feature = "tune")]
+    fn set_tunable(&mut self, name: &str, value: &str) {
+        let Ok(value) = value.parse::<i32>() else {
+            println!("info error: {} must be a number", name);
+            return;
+        };
+        if let Err(e) = parameters::set(name, value) {
+            println!("info error: {}", e);
+            return;
+        }
+        if name.starts_with("lmr_") {
+            self.td.lmr = LmrTable::default();
+        }
+    }
+
+    fn handle_tune_config(&self, tokens: Vec<String>) {
+        match tokens.get(1).map(|t| t.as_str()) {
+            None | Some("ob") => {
+                for tunable in TUNABLES {
+                    println!("{}", tunable.to_openbench());
+                }
+            }
+            Some("wf") => {
+                let entries: Vec<String> = TUNABLES.iter()
+                    .map(|tunable| format!("  {}", tunable.to_weather_factory()))
+                    .collect();
+                println!("{{\n{}\n}}", entries.join(",\n"));
+            }
+            Some(_) => println!("info error: tune-config format must be 'ob' or 'wf'"),
+        }
+    }
+
     fn handle_isready(&self) {
         println!("readyok");
     }
@@ -102,6 +231,7 @@ impl UCI {
             "fen" => tokens
                 .iter()
                 .skip(2)
+                .take_while(|s| *s != "moves")
                 .map(|s| s.as_str())
                 .collect::<Vec<&str>>()
                 .join(" "), // Returns owned String
@@ -111,7 +241,13 @@ impl UCI {
             }
         };
 
-        self.board = Board::from_fen(&fen);
+        let mut board = match Board::from_fen(&fen) {
+            Ok(board) => board,
+            Err(e) => {
+                println!("info string invalid fen: {}", e);
+                return;
+            }
+        };
 
         let moves: Vec<Move> = if let Some(index) = tokens.iter().position(|x| x == "moves") {
             tokens
@@ -123,35 +259,84 @@ impl UCI {
             Vec::new()
         };
 
-        self.td.keys.clear();
-        self.td.root_ply = 0;
-        self.td.keys.push(self.board.hash);
-
-        moves.iter().for_each(|m| {
-            let mut legal_moves = gen_moves(&self.board, MoveFilter::All);
+        // Like an invalid FEN, an illegal move rejects the whole command and leaves the current
+        // position in place.
+        let mut keys = vec![board.hash];
+        for m in &moves {
+            let mut legal_moves = gen_legal_moves(&board, MoveFilter::All);
             let legal_move = legal_moves.iter()
                 .map(|entry| entry.mv)
                 .find(|lm| lm.matches(m));
             match legal_move {
                 Some(m) => {
-                    self.board.make(&m);
-                    self.td.keys.push(self.board.hash);
-                    self.td.root_ply += 1;
+                    board.make(&m);
+                    keys.push(board.hash);
                 }
                 None => {
-                    println!("info error: illegal move {}", m.to_uci());
+                    println!("info string illegal move {}", m.to_uci());
+                    return;
                 }
             }
-        });
+        }
+
+        self.board = board;
+        self.td.root_ply = moves.len();
+        self.td.keys = keys;
     }
 
     fn handle_go(&mut self, tokens: Vec<String>) {
+        if tokens.get(1).is_some_and(|t| t == "perft") {
+            self.handle_go_perft(&tokens);
+            return;
+        }
+
         self.td.reset();
 
-        if tokens.contains(&String::from("movetime")) {
+        let nodes = match self.parse_optional_int(&tokens, "nodes") {
+            Ok(nodes) => nodes,
+            Err(_) => {
+                println!("info error: nodes is not a valid number");
+                return;
+            }
+        };
+
+        // Depth limits are exclusive, so search up to and including the requested depth
+        let depth = match self.parse_optional_int(&tokens, "depth") {
+            Ok(depth) => depth.map(|d| d + 1),
+            Err(_) => {
+                println!("info error: depth is not a valid number");
+                return;
+            }
+        };
+
+        let mate = match self.parse_optional_int(&tokens, "mate") {
+            Ok(mate) => mate.filter(|&n| n > 0),
+            Err(_) => {
+                println!("info error: mate is not a valid number");
+                return;
+            }
+        };
+
+        self.td.search_moves = self.parse_search_moves(&tokens);
+        if !self.td.search_moves.is_empty() && RootMoves::new(&self.board, &self.td.search_moves).is_empty() {
+            println!("info error: none of the searchmoves are legal");
+            return;
+        }
+
+        if self.td.deterministic {
+            // Ignore the clock entirely, and start each search from a clean slate so that
+            // repeating the same command reproduces the same search. Only a node limit is
+            // guaranteed to end the search, as depth and mate limits can take arbitrarily long.
+            if nodes.is_none() {
+                println!("info error: deterministic mode requires a nodes limit");
+                return;
+            }
+            self.td.clear_tables();
+            self.td.limits = SearchLimits::new(None, None, None, None, nodes, depth, self.move_overhead);
+        } else if tokens.contains(&String::from("movetime")) {
             match self.parse_int(&tokens, "movetime") {
                 Ok(movetime) => {
-                    self.td.limits = SearchLimits::new(None, Some(movetime), None, None, None)
+                    self.td.limits = SearchLimits::new(None, None, Some(movetime), None, nodes, depth, self.move_overhead)
                 }
                 Err(_) => {
                     println!("info error: movetime is not a valid number");
@@ -175,38 +360,85 @@ impl UCI {
                 }
             };
 
-            let winc = match self.parse_int(&tokens, "winc") {
-                Ok(winc) => winc,
+            let winc = match self.parse_optional_int(&tokens, "winc") {
+                Ok(winc) => winc.unwrap_or(0),
                 Err(_) => {
                     println!("info error: winc is not a valid number");
                     return;
                 }
             };
 
-            let binc = match self.parse_int(&tokens, "binc") {
-                Ok(binc) => binc,
+            let binc = match self.parse_optional_int(&tokens, "binc") {
+                Ok(binc) => binc.unwrap_or(0),
                 Err(_) => {
                     println!("info error: binc is not a valid number");
                     return;
                 }
             };
 
+            let movestogo = match self.parse_optional_int(&tokens, "movestogo") {
+                Ok(movestogo) => movestogo.filter(|&mtg| mtg > 0),
+                Err(_) => {
+                    println!("info error: movestogo is not a valid number");
+                    return;
+                }
+            };
+
             let (time, inc) = match self.board.stm {
                 White => (wtime, winc),
                 Black => (btime, binc),
             };
 
-            self.td.limits = SearchLimits::new(Some((time, inc)), None, None, None, None);
+            self.td.limits = SearchLimits::new(Some((time, inc)), movestogo, None, None, nodes, depth, self.move_overhead);
+        } else {
+            self.td.limits = SearchLimits::new(None, None, None, None, nodes, depth, self.move_overhead);
+        }
+
+        self.td.limits.mate = mate;
+        self.td.skill = self.skill();
+        self.td.limits.cap(self.td.skill.depth_limit(), self.td.skill.node_limit());
+
+        // Entries from earlier searches were stored with pruning enabled, so they can't be
+        // trusted to prove or rule out a mate.
+        if mate.is_some() {
+            self.td.tt.clear();
         }
 
         // Perform the search
-        search(&self.board, &mut self.td);
+        let (_, score) = search(&self.board, &mut self.td);
+
+        if let Some(mate) = mate {
+            let found = score > 0 && Score::is_mate(score) && Score::mate_in(score) <= mate as i32;
+            if !found && self.td.depth >= 2 * mate as i32 {
+                println!("info string no mate in {}", mate);
+            } else if !found {
+                println!("info string search stopped at depth {} without finding a mate in {}", self.td.depth, mate);
+            }
+        }
 
         // Print the best move
         println!("bestmove {}", self.td.best_move.to_uci());
     }
 
-    fn handle_eval(&mut self) {
+    /// The playing strength, where UCI_LimitStrength takes precedence over the skill level.
+    fn skill(&self) -> Skill {
+        if self.limit_strength {
+            Skill::from_elo(self.elo)
+        } else {
+            Skill::new(self.skill_level)
+        }
+    }
+
+    fn handle_eval(&mut self, tokens: Vec<String>) {
+        if tokens.get(1).is_some_and(|t| t == "trace") {
+            let trace = self.nnue.trace(&self.board);
+            let correction = self.td.correction(&self.board, 0);
+            println!("{}", trace);
+            println!("Correction: {}", correction);
+            println!("Final eval: {} (side to move)", trace.eval + correction);
+            return;
+        }
+        self.nnue.activate(&self.board);
         let eval: i32 = self.nnue.evaluate(&self.board);
         println!("{}", eval);
     }
@@ -215,6 +447,10 @@ impl UCI {
         println!("{}", self.board.to_fen());
     }
 
+    fn handle_display(&self) {
+        println!("{}", self.board);
+    }
+
     fn handle_perft(&self, tokens: Vec<String>) {
         if tokens.len() < 2 {
             println!("info error: missing depth argument");
@@ -229,15 +465,141 @@ impl UCI {
             }
         };
 
+        let Some((threads, table)) = self.parse_perft_options(&tokens) else {
+            return;
+        };
+
         let start = std::time::Instant::now();
-        let nodes = perft(&self.board, depth);
+        let nodes = match depth {
+            0 => 1,
+            _ => divide(&self.board, depth, threads, table.as_ref()).iter().map(|(_, n)| n).sum(),
+        };
         let elapsed = start.elapsed().as_millis();
         println!("info nodes {}", nodes);
         println!("info ms {}", elapsed);
     }
 
+    /// 'go perft <depth>' prints the nodes below each root move, followed by a blank line and the
+    /// total, in the format expected by perftree.
+    fn handle_go_perft(&self, tokens: &[String]) {
+        let depth = match tokens.get(2).map(|t| t.parse::<u8>()) {
+            Some(Ok(depth)) => depth,
+            Some(Err(_)) => {
+                println!("info error: depth argument is not a valid number");
+                return;
+            }
+            None => {
+                println!("info error: missing depth argument");
+                return;
+            }
+        };
+
+        let Some((threads, table)) = self.parse_perft_options(tokens) else {
+            return;
+        };
+
+        let counts = divide(&self.board, depth, threads, table.as_ref());
+        for (mv, nodes) in &counts {
+            println!("{} {}", mv.to_uci(), nodes);
+        }
+        let total: u64 = match depth {
+            0 => 1,
+            _ => counts.iter().map(|(_, n)| n).sum(),
+        };
+        println!();
+        println!("{}", total);
+    }
+
+    /// Run perft on every position in an EPD suite and report the results that don't match.
+    fn handle_perft_suite(&self, tokens: &[String]) {
+        let path = match tokens.iter().position(|t| t == "file") {
+            Some(index) => match tokens.get(index + 1) {
+                Some(path) => path.as_str(),
+                None => {
+                    println!("info error: file is missing a value");
+                    return;
+                }
+            },
+            None => "resources/perft_suite.epd",
+        };
+
+        let max_depth = match self.parse_optional_int(tokens, "depth") {
+            Ok(depth) => depth.map(|d| d.min(u8::MAX as u64) as u8),
+            Err(_) => {
+                println!("info error: depth is not a valid number");
+                return;
+            }
+        };
+
+        let Some((threads, table)) = self.parse_perft_options(tokens) else {
+            return;
+        };
+
+        let cases = match std::fs::read_to_string(path) {
+            Ok(epd) => match parse_epd(&epd, max_depth) {
+                Ok(cases) => cases,
+                Err(e) => {
+                    println!("info error: {}: {}", path, e);
+                    return;
+                }
+            },
+            Err(e) => {
+                println!("info error: could not read {}: {}", path, e);
+                return;
+            }
+        };
+
+        let start = std::time::Instant::now();
+        let mut passed = 0;
+        let mut total_nodes = 0;
+        for case in &cases {
+            let board = match Board::from_fen(&case.fen) {
+                Ok(board) => board,
+                Err(e) => {
+                    println!("info string invalid fen: {} ({})", case.fen, e);
+                    continue;
+                }
+            };
+            let nodes: u64 = divide(&board, case.depth, threads, table.as_ref()).iter().map(|(_, n)| n).sum();
+            let nodes = if case.depth == 0 { 1 } else { nodes };
+            total_nodes += nodes;
+            if nodes == case.nodes {
+                passed += 1;
+            } else {
+                println!("mismatch: {} depth {} expected {} got {}", case.fen, case.depth, case.nodes, nodes);
+            }
+        }
+        let elapsed = start.elapsed().as_millis();
+        println!("perft suite: {}/{} passed", passed, cases.len());
+        println!("info nodes {}", total_nodes);
+        println!("info ms {}", elapsed);
+    }
+
+    /// The optional 'threads' and 'hash' (in MB) arguments shared by the perft commands. The
+    /// hash table is off unless a size is given.
+    fn parse_perft_options(&self, tokens: &[String]) -> Option<(usize, Option<PerftTable>)> {
+        let threads = match self.parse_optional_int(tokens, "threads") {
+            Ok(threads) => threads.unwrap_or(1).max(1) as usize,
+            Err(_) => {
+                println!("info error: threads is not a valid number");
+                return None;
+            }
+        };
+        let table = match self.parse_optional_int(tokens, "hash") {
+            Ok(Some(size)) if size > 0 => Some(PerftTable::new(size as usize)),
+            Ok(_) => None,
+            Err(_) => {
+                println!("info error: hash is not a valid number");
+                return None;
+            }
+        };
+        Some((threads, table))
+    }
+
+    /// The search this was meant for has already been cancelled by the reader thread, so let the
+    /// next one run.
     fn handle_stop(&mut self) {
-        //self.td.cancelled = true;
+        self.td.cancelled.store(false, Ordering::Relaxed);
     }
 
     fn handle_help(&self) {
@@ -249,8 +611,12 @@ impl UCI {
         println!("position    -- set up the board position");
         println!("go          -- start searching for the best move");
         println!("stop        -- stop searching and return the best move");
-        println!("eval        -- evaluate the current position");
-        println!("perft       -- run perft on the current position");
+        println!("fen         -- print the FEN of the current position");
+        println!("d           -- display the current position");
+        println!("eval        -- evaluate the current position ('eval trace' for a breakdown)");
+        println!("perft       -- run perft on the current position ('go perft' to divide by root move)");
+        println!("perft-suite -- run perft on every position in the perft suite and report mismatches");
+        println!("tune-config -- print the tunable parameters as SPSA input ('ob' or 'wf')");
         println!("quit        -- exit the application");
     }
 
@@ -258,6 +624,28 @@ impl UCI {
         std::process::exit(0);
     }
 
+    /// The moves following 'searchmoves', up to the next go parameter.
+    fn parse_search_moves(&self, tokens: &[String]) -> Vec<Move> {
+        const GO_PARAMS: [&str; 11] = [
+            "wtime", "btime", "winc", "binc", "movestogo", "depth", "nodes", "mate", "movetime", "infinite", "ponder"
+        ];
+        match tokens.iter().position(|t| t == "searchmoves") {
+            Some(index) => tokens[index + 1..].iter()
+                .take_while(|t| !GO_PARAMS.contains(&t.as_str()))
+                .map(|t| Move::parse_uci(t))
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    fn parse_optional_int(&self, tokens: &[String], name: &str) -> Result<Option<u64>, String> {
+        if tokens.iter().any(|x| x == name) {
+            self.parse_int(tokens, name).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
     fn parse_int(&self, tokens: &[String], name: &str) -> Result<u64, String> {
         match tokens.iter().position(|x| x == name) {
             Some(index) => match tokens.get(index + 1) {
@@ -271,3 +659,152 @@ impl UCI {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::search::{search, Score};
+    use crate::skill::Skill;
+    use crate::time::SearchLimits;
+    use crate::uci::UCI;
+    use std::sync::atomic::Ordering;
+    use std::thread;
+    use std::time::Duration;
+
+    // Black to move can play f6g8 to repeat the start position for the third time.
+    const REPETITION: &str = "position startpos moves g1f3 g8f6 f3g1 f6g8 g1f3 g8f6 f3g1";
+
+    #[test]
+    fn test_position_moves_history() {
+        let mut uci = UCI::new();
+        uci.handle_position(tokens(REPETITION));
+        assert_eq!(uci.td.keys.len(), 8);
+        assert_eq!(uci.td.root_ply, 7);
+        assert_eq!(uci.td.keys.last(), Some(&uci.board.hash));
+        assert_eq!(uci.td.keys[0], uci.td.keys[4]);
+    }
+
+    #[test]
+    fn test_position_fen() {
+        let mut uci = UCI::new();
+        uci.handle_position(tokens("position fen 4k3/8/8/8/8/8/4P3/4K3 w - - 0 1 moves e2e4"));
+        assert_eq!(uci.board.to_fen(), "4k3/8/8/8/4P3/8/8/4K3 b - e3 0 1");
+
+        // An invalid FEN is reported and leaves the current position in place
+        uci.handle_position(tokens("position fen 4k3/8/8/8/8/8/4P3/8 w - - 0 1"));
+        assert_eq!(uci.board.to_fen(), "4k3/8/8/8/4P3/8/8/4K3 b - e3 0 1");
+        uci.handle_position(tokens("position fen 4k3/8/8"));
+        assert_eq!(uci.board.to_fen(), "4k3/8/8/8/4P3/8/8/4K3 b - e3 0 1");
+    }
+
+    #[test]
+    fn test_position_illegal_move() {
+        let mut uci = UCI::new();
+        uci.handle_position(tokens("position startpos moves e2e4"));
+        let keys = uci.td.keys.clone();
+
+        // e7e5 is fine but e4e6 is not, so the whole command is rejected
+        uci.handle_position(tokens("position startpos moves e2e4 e7e5 e4e6 d7d6"));
+        assert_eq!(uci.board.to_fen(), "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1");
+        assert_eq!(uci.td.keys, keys);
+        assert_eq!(uci.td.root_ply, 1);
+    }
+
+    #[test]
+    fn test_negative_contempt_seeks_repetition() {
+        let (best_move, score) = search_with_contempt(REPETITION, -100);
+        assert_eq!(best_move, "f6g8");
+        assert!((99..=101).contains(&score), "score {}", score);
+    }
+
+    #[test]
+    fn test_positive_contempt_avoids_repetition() {
+        let (best_move, score) = search_with_contempt(REPETITION, 100);
+        assert_ne!(best_move, "f6g8");
+        assert!(score > -99, "score {}", score);
+    }
+
+    #[test]
+    fn test_skill_level_scores_every_root_move() {
+        let mut uci = UCI::new();
+        uci.skill_level = 0;
+        uci.handle_position(tokens("position startpos"));
+        uci.handle_go(tokens("go"));
+        assert_eq!(uci.td.depth, 2);
+        assert_eq!(uci.td.root_moves.len(), 20);
+        assert!(uci.td.root_moves.iter().all(|rm| rm.score > Score::MIN));
+    }
+
+    #[test]
+    fn test_searchmoves() {
+        let mut uci = UCI::new();
+        uci.handle_position(tokens("position startpos"));
+        uci.handle_go(tokens("go searchmoves a2a3 h2h4 depth 3"));
+        assert_eq!(uci.td.root_moves.len(), 2);
+        assert!(["a2a3", "h2h4"].contains(&uci.td.best_move.to_uci().as_str()));
+
+        uci.handle_go(tokens("go depth 3"));
+        assert_eq!(uci.td.root_moves.len(), 20);
+    }
+
+    #[test]
+    fn test_limit_strength_overrides_skill_level() {
+        let mut uci = UCI::new();
+        uci.skill_level = 10;
+        uci.elo = Skill::MIN_ELO;
+        assert_eq!(uci.skill(), Skill::new(10));
+        uci.limit_strength = true;
+        assert_eq!(uci.skill(), Skill::new(0));
+    }
+
+    fn search_with_contempt(position: &str, contempt: i32) -> (String, i32) {
+        let mut uci = UCI::new();
+        uci.td.contempt = contempt;
+        uci.handle_position(tokens(position));
+        uci.td.limits = SearchLimits::new(None, None, None, None, None, Some(6), 0);
+        uci.td.reset();
+        let (best_move, score) = search(&uci.board, &mut uci.td);
+        (best_move.to_uci(), score)
+    }
+
+    #[test]
+    fn test_deterministic_requires_nodes() {
+        let mut uci = UCI::new();
+        uci.td.deterministic = true;
+        uci.handle_position(tokens("position startpos"));
+        uci.handle_go(tokens("go mate 2"));
+        assert!(!uci.td.best_move.exists());
+        uci.handle_go(tokens("go depth 3"));
+        assert!(!uci.td.best_move.exists());
+        uci.handle_go(tokens("go mate 2 nodes 5000"));
+        assert!(uci.td.best_move.exists());
+    }
+
+    #[test]
+    fn test_go_mate_without_mate() {
+        // No side can force mate from the start position, so the search must run out of depth
+        let mut uci = UCI::new();
+        uci.handle_position(tokens("position startpos"));
+        uci.handle_go(tokens("go mate 2"));
+        assert_eq!(uci.td.depth, 4);
+        assert!(uci.td.best_move.exists());
+    }
+
+    #[test]
+    fn test_stop_cancels_search() {
+        let mut uci = UCI::new();
+        uci.handle_position(tokens("position startpos"));
+        let cancelled = uci.td.cancelled.clone();
+        let stopper = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(200));
+            cancelled.store(true, Ordering::Relaxed);
+        });
+        uci.handle_go(tokens("go infinite"));
+        stopper.join().unwrap();
+        assert!(uci.td.best_move.exists());
+    }
+
+    fn tokens(command: &str) -> Vec<String> {
+        command.split_whitespace().map(String::from).collect()
+    }
+
+}